@@ -0,0 +1,458 @@
+use crate::shell::Shell;
+
+// How deep a variable's value may refer to further variables (a=b, b=a...)
+const MAX_VAR_DEPTH: usize = 32;
+
+// Operator spellings, longest first so the longest match wins
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "++", "--",
+    "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=",
+    "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "?", ":", "=", ",", "(", ")",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Name(Lvalue),
+    Op(&'static str),
+}
+
+// A variable, or with a subscript an array element (`a[i+1]`). The
+// subscript is kept as text for get_element / set_element to evaluate.
+#[derive(Debug, Clone, PartialEq)]
+struct Lvalue {
+    name: String,
+    subscript: Option<String>,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(i64),
+    Var(Lvalue),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    // `op` is "=" or a compound assignment such as "+="
+    Assign(&'static str, Lvalue, Box<Expr>),
+    // ++x / --x when prefix, otherwise x++ / x--
+    Step { target: Lvalue, delta: i64, prefix: bool },
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+/// Evaluate an arithmetic expression as in `$(( ))` and `(( ))`. Names
+/// refer to shell variables or, as `name[expr]`, array elements (no `$`
+/// needed; unset or empty is 0) and the assignment operators update them. Errors such as division by zero come
+/// back as a message.
+pub fn evaluate(text: &str, shell: &mut Shell) -> Result<i64, String> {
+    evaluate_at_depth(text, shell, 0)
+}
+
+fn evaluate_at_depth(text: &str, shell: &mut Shell, depth: usize) -> Result<i64, String> {
+    let tokens = tokenize(text)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { tokens: &tokens, pos: 0 };
+    let expr = parser.comma()?;
+    if let Some(token) = tokens.get(parser.pos) {
+        return Err(format!("syntax error in expression (error token is \"{}\")", token_text(token)));
+    }
+    eval(&expr, shell, depth)
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '_' | '#')) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if c.is_ascii_digit() {
+                tokens.push(Token::Num(parse_number(&word)?));
+                continue;
+            }
+            let subscript = if chars.get(i) == Some(&'[') {
+                let end = closing_bracket(&chars, i + 1)
+                    .ok_or_else(|| format!("bad array subscript (error token is \"{}\")", chars[start..].iter().collect::<String>()))?;
+                let subscript = chars[i + 1..end].iter().collect();
+                i = end + 1;
+                Some(subscript)
+            } else {
+                None
+            };
+            tokens.push(Token::Name(Lvalue { name: word, subscript }));
+        } else if let Some(op) = OPERATORS.iter().find(|op| {
+            op.chars().eq(chars[i..].iter().take(op.len()).copied())
+        }) {
+            tokens.push(Token::Op(op));
+            i += op.len();
+        } else {
+            return Err(format!("syntax error: invalid arithmetic operator (error token is \"{}\")", c));
+        }
+    }
+    Ok(tokens)
+}
+
+// Index of the `]` that closes a subscript starting at chars[start]
+fn closing_bracket(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, &c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 0 => return Some(i),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Decimal, 0x hex, leading-0 octal or base#digits
+fn parse_number(word: &str) -> Result<i64, String> {
+    let invalid = || format!("{}: value too great for base (error token is \"{}\")", word, word);
+    let (base, digits) = if let Some((base, digits)) = word.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base) if (2..=36).contains(&base) => (base, digits),
+            _ => return Err(format!("{}: invalid arithmetic base", word)),
+        }
+    } else if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        (16, hex)
+    } else if word.len() > 1 && word.starts_with('0') {
+        (8, &word[1..])
+    } else {
+        (10, word)
+    };
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+    digits.chars().try_fold(0i64, |n, c| {
+        let digit = c.to_digit(base).ok_or_else(invalid)?;
+        Ok(n.wrapping_mul(base as i64).wrapping_add(digit as i64))
+    })
+}
+
+fn token_text(token: &Token) -> String {
+    match token {
+        Token::Num(n) => n.to_string(),
+        Token::Name(target) => target.to_string(),
+        Token::Op(op) => op.to_string(),
+    }
+}
+
+impl std::fmt::Display for Lvalue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.subscript {
+            Some(subscript) => write!(f, "{}[{}]", self.name, subscript),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+// Binary operators from loosest to tightest binding, starting at `||`.
+// Assignment, `?:` and `**` are handled separately.
+const BINARY_LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(match self.tokens.get(self.pos) {
+                Some(token) => format!("syntax error in expression (error token is \"{}\")", token_text(token)),
+                None => String::from("syntax error: operand expected"),
+            })
+        }
+    }
+
+    fn comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.assignment()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(self.assignment()?));
+        }
+        Ok(expr)
+    }
+
+    fn assignment(&mut self) -> Result<Expr, String> {
+        if let Some(Token::Name(target)) = self.tokens.get(self.pos)
+            && let Some(Token::Op(op)) = self.tokens.get(self.pos + 1)
+            && op.ends_with('=')
+            && !matches!(*op, "==" | "!=" | "<=" | ">=")
+        {
+            self.pos += 2;
+            return Ok(Expr::Assign(op, target.clone(), Box::new(self.assignment()?)));
+        }
+        self.conditional()
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let cond = self.binary(0)?;
+        if self.peek_op() != Some("?") {
+            return Ok(cond);
+        }
+        self.pos += 1;
+        let then = self.comma()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Cond(Box::new(cond), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == BINARY_LEVELS.len() {
+            return self.power();
+        }
+        let mut expr = self.binary(level + 1)?;
+        while let Some(op) = self.peek_op().filter(|op| BINARY_LEVELS[level].contains(op)) {
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.binary(level + 1)?));
+        }
+        Ok(expr)
+    }
+
+    // `**` is right-associative
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.unary()?;
+        if self.peek_op() == Some("**") {
+            self.pos += 1;
+            return Ok(Expr::Binary("**", Box::new(base), Box::new(self.power()?)));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Name(target)) => {
+                        self.pos += 1;
+                        Ok(Expr::Step { target: target.clone(), delta: if op == "++" { 1 } else { -1 }, prefix: true })
+                    }
+                    // `--5` is two negations
+                    _ => {
+                        let sign = if op == "++" { "+" } else { "-" };
+                        let inner = Expr::Unary(sign, Box::new(self.unary()?));
+                        Ok(Expr::Unary(sign, Box::new(inner)))
+                    }
+                }
+            }
+            Some(op @ ("+" | "-" | "!" | "~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Num(n)) => {
+                self.pos += 1;
+                Ok(Expr::Num(*n))
+            }
+            Some(Token::Name(target)) => {
+                self.pos += 1;
+                if let Some(op @ ("++" | "--")) = self.peek_op() {
+                    self.pos += 1;
+                    return Ok(Expr::Step { target: target.clone(), delta: if op == "++" { 1 } else { -1 }, prefix: false });
+                }
+                Ok(Expr::Var(target.clone()))
+            }
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let expr = self.comma()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(token) => Err(format!("syntax error: operand expected (error token is \"{}\")", token_text(token))),
+            None => Err(String::from("syntax error: operand expected")),
+        }
+    }
+}
+
+fn eval(expr: &Expr, shell: &mut Shell, depth: usize) -> Result<i64, String> {
+    Ok(match expr {
+        Expr::Num(n) => *n,
+        Expr::Var(target) => variable(target, shell, depth)?,
+        Expr::Unary(op, operand) => {
+            let value = eval(operand, shell, depth)?;
+            match *op {
+                "-" => value.wrapping_neg(),
+                "!" => (value == 0) as i64,
+                "~" => !value,
+                _ => value,
+            }
+        }
+        // the logical operators only evaluate their right side when needed
+        Expr::Binary("&&", left, right) => {
+            (eval(left, shell, depth)? != 0 && eval(right, shell, depth)? != 0) as i64
+        }
+        Expr::Binary("||", left, right) => {
+            (eval(left, shell, depth)? != 0 || eval(right, shell, depth)? != 0) as i64
+        }
+        Expr::Binary(op, left, right) => {
+            let left = eval(left, shell, depth)?;
+            let right = eval(right, shell, depth)?;
+            apply(op, left, right)?
+        }
+        Expr::Cond(cond, then, otherwise) => {
+            if eval(cond, shell, depth)? != 0 { eval(then, shell, depth)? } else { eval(otherwise, shell, depth)? }
+        }
+        Expr::Assign(op, target, value) => {
+            let value = eval(value, shell, depth)?;
+            let value = match op.strip_suffix('=').filter(|op| !op.is_empty()) {
+                Some(op) => apply(op, variable(target, shell, depth)?, value)?,
+                None => value,
+            };
+            assign(target, value, shell)?;
+            value
+        }
+        Expr::Step { target, delta, prefix } => {
+            let old = variable(target, shell, depth)?;
+            let new = old.wrapping_add(*delta);
+            assign(target, new, shell)?;
+            if *prefix { new } else { old }
+        }
+    })
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    Ok(match op {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err(String::from("division by 0")),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err(String::from("exponent less than 0")),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "&" => left & right,
+        "^" => left ^ right,
+        "|" => left | right,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        _ => right, // ","
+    })
+}
+
+// A variable's or element's value as a number. The value may itself be an
+// expression (`a=b+1`), which is evaluated in turn.
+fn variable(target: &Lvalue, shell: &mut Shell, depth: usize) -> Result<i64, String> {
+    let value = match &target.subscript {
+        Some(subscript) => shell.get_element(&target.name, subscript)?,
+        None => shell.get_var(&target.name),
+    };
+    let value = value.unwrap_or_default();
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    if let Ok(n) = value.parse() {
+        return Ok(n);
+    }
+    if depth >= MAX_VAR_DEPTH {
+        return Err(format!("{}: expression recursion level exceeded", target));
+    }
+    evaluate_at_depth(value, shell, depth + 1)
+}
+
+fn assign(target: &Lvalue, value: i64, shell: &mut Shell) -> Result<(), String> {
+    match &target.subscript {
+        Some(subscript) => shell.set_element(&target.name, subscript, &value.to_string()),
+        None => shell.set_var(&target.name, &value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_str(text: &str) -> Result<i64, String> {
+        evaluate(text, &mut Shell::new())
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(eval_str("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval_str("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval_str("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval_str("-2 ** 2"), Ok(4));
+        assert_eq!(eval_str("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval_str("1 < 2 && 3 > 4 || 5 == 5"), Ok(1));
+        assert_eq!(eval_str("7 & 3 | 8 ^ 1"), Ok(11));
+        assert_eq!(eval_str("1 << 4 >> 2"), Ok(4));
+        assert_eq!(eval_str("0 ? 1 : 2 ? 3 : 4"), Ok(3));
+        assert_eq!(eval_str("!0 + ~0"), Ok(0));
+        assert_eq!(eval_str("0x1f + 010 + 2#101"), Ok(44));
+    }
+
+    #[test]
+    fn variables_and_assignment() {
+        let mut shell = Shell::new();
+        assert_eq!(evaluate("arith_test_x = 5, arith_test_x += 2, arith_test_x", &mut shell), Ok(7));
+        assert_eq!(evaluate("arith_test_x++ + ++arith_test_x", &mut shell), Ok(16));
+        assert_eq!(evaluate("arith_test_unset * 3", &mut shell), Ok(0));
+        // the skipped branch doesn't run its assignment
+        assert_eq!(evaluate("0 && (arith_test_x = 100)", &mut shell), Ok(0));
+        assert_eq!(shell.get_var("arith_test_x"), Some(String::from("9")));
+    }
+
+    #[test]
+    fn array_elements() {
+        let mut shell = Shell::new();
+        shell.set_element("arith_a", "1", "10").unwrap();
+        shell.set_var("arith_i", "1").unwrap();
+        assert_eq!(evaluate("arith_a[1] + arith_a[arith_i - 1]", &mut shell), Ok(10));
+        assert_eq!(evaluate("arith_a[arith_i]++", &mut shell), Ok(10));
+        assert_eq!(evaluate("arith_a[(arith_i + 1) * 2] = arith_a[1] * 2", &mut shell), Ok(22));
+        assert_eq!(evaluate("arith_a[4] += 1, arith_a[-1]", &mut shell), Ok(23));
+        assert_eq!(shell.get_element("arith_a", "1"), Ok(Some(String::from("11"))));
+        assert!(evaluate("arith_a[1", &mut shell).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(eval_str("1 / 0"), Err(String::from("division by 0")));
+        assert_eq!(eval_str("5 % (2 - 2)"), Err(String::from("division by 0")));
+        assert!(eval_str("1 +").is_err());
+        assert!(eval_str("(1").is_err());
+        assert!(eval_str("2 3").is_err());
+        assert!(eval_str("08").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+/// One piece of a shell word. Adjacent pieces are concatenated when the word
+/// is expanded; the variant records how the piece was quoted so expansion
+/// only happens where the quoting rules allow it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    Literal(String), // unquoted text
+    Quoted(String),  // text from '...', "..." or a backslash escape, taken verbatim
+    Tilde(String),   // leading unquoted ~ prefix; holds the user name ("" for $HOME)
+    // $NAME, $? or ${...}; `quoted` is set inside double quotes
+    Param { expr: Box<ParamExpr>, quoted: bool },
+    // $(command) or `command`, kept as source text and parsed when it runs.
+    // Unquoted output is split into separate fields.
+    CommandSub { command: String, quoted: bool },
+    // $(( expr )); the expression text is expanded, then evaluated
+    Arith(Word),
+    // (a [k]=b ...) right after the `=` of an assignment
+    Array(Vec<ArrayItem>),
+}
+
+pub type Word = Vec<WordPart>;
+
+/// One item of an array assignment: `value`, or `[key]=value`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayItem {
+    pub key: Option<Word>,
+    pub value: Word,
+}
+
+/// A parameter expansion. Plain `$NAME` is `ParamOp::Plain`; `index` is
+/// the subscript of `${NAME[...]}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamExpr {
+    pub name: String,
+    pub index: Option<Subscript>,
+    pub op: ParamOp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Subscript {
+    All,       // [@]: every element, as separate fields when quoted
+    Joined,    // [*]: every element, joined into one field when quoted
+    Key(Word), // [expr] or [key]
+}
+
+/// What a `${...}` expansion does with the parameter's value. `colon` marks
+/// the `:-` style forms, which treat an empty value like an unset one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamOp {
+    Plain,                                  // ${NAME}
+    Length,                                 // ${#NAME}, or ${#NAME[@]} for the element count
+    Keys,                                   // ${!NAME[@]}: the indices or keys of an array
+    Default { word: Word, colon: bool },     // ${NAME:-word}
+    Assign { word: Word, colon: bool },      // ${NAME:=word}
+    Error { word: Word, colon: bool },       // ${NAME:?word}
+    Alternative { word: Word, colon: bool }, // ${NAME:+word}
+    RemovePrefix { pattern: Word, longest: bool }, // ${NAME#pat} / ${NAME##pat}
+    RemoveSuffix { pattern: Word, longest: bool }, // ${NAME%pat} / ${NAME%%pat}
+    Replace { pattern: Word, replacement: Word, all: bool }, // ${NAME/pat/rep} / ${NAME//pat/rep}
+    Substring { offset: Word, length: Option<Word> },        // ${NAME:offset:length}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirKind {
+    Input,     // < file
+    Output,    // > file (also >|)
+    Append,    // >> file
+    ReadWrite, // <> file
+    DupInput,  // <& n, or <& - to close
+    DupOutput, // >& n, or >& - to close
+    OutputAll, // &> file: stdout and stderr
+    AppendAll, // &>> file
+    HereDoc,    // << / <<-: the target word is the document body
+    HereString, // <<< word
+}
+
+impl RedirKind {
+    /// The fd a redirection applies to when no number is written before it.
+    pub fn default_fd(self) -> i32 {
+        match self {
+            RedirKind::Input | RedirKind::ReadWrite | RedirKind::DupInput
+            | RedirKind::HereDoc | RedirKind::HereString => 0,
+            _ => 1,
+        }
+    }
+}
+
+/// A redirection such as `2>>log`. `fd` is the explicit number before the
+/// operator, if any. A command's redirections are applied in source order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: Option<i32>,
+    pub kind: RedirKind,
+    pub target: Word,
+}
+
+/// `NAME=value` before a command (or on its own). The value is expanded
+/// without field splitting or pathname expansion. `NAME[index]=value` sets
+/// one array element and `+=` appends.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub index: Option<Word>,
+    pub append: bool,
+    pub value: Word,
+}
+
+/// `words` holds the command name and its arguments, still unexpanded.
+/// Assignments without any words set shell variables; before a command they
+/// only apply to that command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Arith(Word), // (( expr )): succeeds when the result is non-zero
+    // redirections after the closing word apply to the whole command
+    Compound(CompoundCommand, Vec<Redirect>),
+    Function(FunctionDef), // name() compound-command
+}
+
+/// A function definition. `text` is the definition as written, which
+/// `declare -f` prints.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDef {
+    pub name: String,
+    pub body: Box<Command>,
+    pub text: String,
+}
+
+/// A command built from lists, delimited by reserved words.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompoundCommand {
+    Group(List),    // { list; }: run in the current shell
+    Subshell(List), // ( list ): run in a forked copy of the shell
+    If(IfCommand),
+    While(WhileCommand),
+    For(ForCommand),
+    ArithFor(ArithForCommand),
+    Case(CaseCommand),
+}
+
+/// `if c1; then b1; elif c2; then b2; else b3; fi`: the body of the first
+/// condition that succeeds runs, else `otherwise`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IfCommand {
+    pub branches: Vec<(List, List)>, // (condition, body)
+    pub otherwise: Option<List>,
+}
+
+/// `while cond; do body; done`, or `until` when `until` is set: the body
+/// runs for as long as the condition succeeds (fails, for `until`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhileCommand {
+    pub condition: List,
+    pub body: List,
+    pub until: bool,
+}
+
+/// `for name in words; do body; done`. Without `in`, `words` is None and the
+/// loop runs over the positional parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForCommand {
+    pub name: String,
+    pub words: Option<Vec<Word>>,
+    pub body: List,
+}
+
+/// `case word in pattern | pattern) list ;; ... esac`: the list of the
+/// first item with a pattern matching the word runs, then the terminator
+/// says what happens next.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseCommand {
+    pub word: Word,
+    pub items: Vec<CaseItem>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+    pub terminator: CaseTerminator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseTerminator {
+    Break,       // ;; (or the last item before esac): stop
+    FallThrough, // ;& : also run the next item's list without matching
+    Continue,    // ;;&: go on testing the following patterns
+}
+
+/// `for ((init; condition; step)); do body; done`. Each part is arithmetic
+/// text; an empty condition is true.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArithForCommand {
+    pub init: Word,
+    pub condition: Word,
+    pub step: Word,
+    pub body: List,
+}
+
+/// Commands joined by `|`; a single command is a pipeline of one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And, // &&
+    Or,  // ||
+}
+
+/// Pipelines chained with `&&` / `||`, evaluated left to right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+}
+
+/// One entry of a list. `text` is the source text, used when reporting jobs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListItem {
+    pub and_or: AndOr,
+    pub background: bool,
+    pub text: String,
+}
+
+/// Entries separated by `;` or `&`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    pub items: Vec<ListItem>,
+}
@@ -0,0 +1,184 @@
+use crate::ast::{Word, WordPart};
+
+// A word broken into the pieces brace expansion looks at: characters of
+// unquoted text, and everything else (quoted text, expansions) as opaque parts
+// that are copied into every result
+#[derive(Clone)]
+enum Unit {
+    Char(char),
+    Part(WordPart),
+}
+
+// The unit sequences a brace expression expands to, in order
+type Alternatives = Vec<Vec<Unit>>;
+
+/// Brace-expand a word: `a{b,c}d` becomes `abd acd`, `{1..3}` becomes
+/// `1 2 3`. Only unquoted braces count. Words without a valid brace
+/// expression come back unchanged.
+pub fn brace_expand(word: &Word) -> Vec<Word> {
+    let units: Vec<Unit> = word
+        .iter()
+        .flat_map(|part| match part {
+            WordPart::Literal(text) => text.chars().map(Unit::Char).collect(),
+            other => vec![Unit::Part(other.clone())],
+        })
+        .collect();
+    expand_units(&units).into_iter().map(|units| to_word(&units)).collect()
+}
+
+fn expand_units(units: &[Unit]) -> Vec<Vec<Unit>> {
+    let mut start = 0;
+    while let Some((open, close, alternatives)) = find_brace(units, start) {
+        if let Some(alternatives) = alternatives {
+            let (preamble, postscript) = (&units[..open], &units[close + 1..]);
+            // each result is expanded again, which handles nested braces in
+            // the alternative and further braces in the postscript
+            return alternatives
+                .into_iter()
+                .flat_map(|alternative| {
+                    let combined: Vec<Unit> = preamble.iter().chain(&alternative).chain(postscript).cloned().collect();
+                    expand_units(&combined)
+                })
+                .collect();
+        }
+        start = open + 1;
+    }
+    vec![units.to_vec()]
+}
+
+// Find the first `{` at or after `start` with a matching `}`. Returns its
+// position, the closing position and the alternatives it expands to, or None
+// for them if the braces hold neither a comma list nor a sequence.
+fn find_brace(units: &[Unit], start: usize) -> Option<(usize, usize, Option<Alternatives>)> {
+    let open = start + units[start..].iter().position(|u| matches!(u, Unit::Char('{')))?;
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (i, unit) in units.iter().enumerate().skip(open + 1) {
+        match unit {
+            Unit::Char('{') => depth += 1,
+            Unit::Char('}') if depth > 0 => depth -= 1,
+            Unit::Char('}') => {
+                let alternatives = if commas.is_empty() {
+                    sequence(&units[open + 1..i])
+                } else {
+                    let mut bounds = vec![open];
+                    bounds.extend(&commas);
+                    bounds.push(i);
+                    Some(bounds.windows(2).map(|w| units[w[0] + 1..w[1]].to_vec()).collect())
+                };
+                return Some((open, i, alternatives));
+            }
+            Unit::Char(',') if depth == 0 => commas.push(i),
+            _ => {}
+        }
+    }
+    // never closed: a later `{` may still start a complete expression
+    find_brace(units, open + 1)
+}
+
+// `x..y` or `x..y..step` with integer or single-letter ends
+fn sequence(units: &[Unit]) -> Option<Alternatives> {
+    let text = units
+        .iter()
+        .map(|u| match u {
+            Unit::Char(c) => Some(*c),
+            Unit::Part(_) => None,
+        })
+        .collect::<Option<String>>()?;
+    let fields: Vec<&str> = text.split("..").collect();
+    if fields.len() != 2 && fields.len() != 3 {
+        return None;
+    }
+    let step: i64 = match fields.get(2) {
+        Some(step) => step.parse::<i64>().ok()?.checked_abs()?.max(1),
+        None => 1,
+    };
+
+    let items: Vec<String> = if let (Ok(from), Ok(to)) = (fields[0].parse::<i64>(), fields[1].parse::<i64>()) {
+        // a leading zero on either end pads every number to the same width
+        let padded = |s: &str| s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0');
+        let width = if padded(fields[0]) || padded(fields[1]) { fields[0].len().max(fields[1].len()) } else { 0 };
+        range(from, to, step)?.map(|n| format!("{:0width$}", n, width = width)).collect()
+    } else {
+        let letter = |s: &str| {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphabetic() => Some(c as i64),
+                _ => None,
+            }
+        };
+        let (from, to) = (letter(fields[0])?, letter(fields[1])?);
+        range(from, to, step)?.map(|n| ((n as u8) as char).to_string()).collect()
+    };
+    Some(items.into_iter().map(|item| item.chars().map(Unit::Char).collect()).collect())
+}
+
+// from..=to counting up or down by step; None if the distance overflows
+fn range(from: i64, to: i64, step: i64) -> Option<impl Iterator<Item = i64>> {
+    let count = from.checked_sub(to)?.checked_abs()? / step + 1;
+    let step = if to < from { -step } else { step };
+    Some((0..count).map(move |i| from + i * step))
+}
+
+fn to_word(units: &[Unit]) -> Word {
+    let mut word = Vec::new();
+    for unit in units {
+        match unit {
+            Unit::Char(c) => match word.last_mut() {
+                Some(WordPart::Literal(text)) => text.push(*c),
+                _ => word.push(WordPart::Literal(c.to_string())),
+            },
+            Unit::Part(part) => word.push(part.clone()),
+        }
+    }
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(text: &str) -> Vec<String> {
+        brace_expand(&vec![WordPart::Literal(text.to_string())])
+            .iter()
+            .map(|word| match word.as_slice() {
+                [WordPart::Literal(text)] => text.clone(),
+                [] => String::new(),
+                other => panic!("unexpected parts {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn comma_lists() {
+        assert_eq!(expand("file.{txt,bak}"), ["file.txt", "file.bak"]);
+        assert_eq!(expand("src/{a,b}/{x,y}"), ["src/a/x", "src/a/y", "src/b/x", "src/b/y"]);
+        assert_eq!(expand("{a,b{1,2}}c"), ["ac", "b1c", "b2c"]);
+        assert_eq!(expand("{a}{b,c}"), ["{a}b", "{a}c"]);
+        assert_eq!(expand("{a,b"), ["{a,b"]);
+        assert_eq!(expand("{a{b,c}"), ["{ab", "{ac"]);
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(expand("{1..4}"), ["1", "2", "3", "4"]);
+        assert_eq!(expand("{3..1}"), ["3", "2", "1"]);
+        assert_eq!(expand("{08..11}"), ["08", "09", "10", "11"]);
+        assert_eq!(expand("{a..e..2}"), ["a", "c", "e"]);
+        assert_eq!(expand("{1..20..5}"), ["1", "6", "11", "16"]);
+        assert_eq!(expand("{1..a}"), ["{1..a}"]);
+        assert_eq!(expand("{1..3..-9223372036854775808}"), ["{1..3..-9223372036854775808}"]);
+        assert_eq!(expand("{-9223372036854775808..1}"), ["{-9223372036854775808..1}"]);
+    }
+
+    #[test]
+    fn quoted_braces_stay() {
+        let word = vec![
+            WordPart::Literal("a".to_string()),
+            WordPart::Quoted("{x,y}".to_string()),
+            WordPart::Literal("{1,2}".to_string()),
+        ];
+        assert_eq!(brace_expand(&word).len(), 2);
+        assert_eq!(brace_expand(&word)[0][1], WordPart::Quoted("{x,y}".to_string()));
+    }
+}
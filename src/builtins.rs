@@ -1,32 +1,14 @@
+use std::collections::BTreeMap;
 use std::env;
 use crate::job::JobTable;
+use crate::shell::{Control, Options, Shell};
+use crate::vars::{is_name, ArrayItem, Attrs, Value, Var};
 
+/// What a declaration builtin's `name=(...)` argument assigns, the items
+/// already expanded; None for any other argument.
+pub type ArrayArg = Option<Vec<ArrayItem>>;
 
-fn expand_cd_arg(s: &str) -> String {
-    if s == "~" {
-        return env::var("HOME").unwrap_or_else(|_| "/".to_string());
-    }
-    if let Some(rest) = s.strip_prefix("~/") {
-        let home = env::var("HOME").unwrap_or_else(|_| "/".to_string());
-        return format!("{}/{}", home, rest);
-    }
-    if let Some(var) = s.strip_prefix('$') {
-        return env::var(var).unwrap_or_else(|_| s.to_string());
-    }
-    s.to_string()
-}
-
-
-
-
-
-
-
-
-
-
-
-pub enum BuiltinResult { Handled, NotHandled }
+pub enum BuiltinResult { Handled(i32), NotHandled } // Handled carries the exit status
 
 pub struct History {
     buf: Vec<String>, // we’ll keep up to 3 recent valid commands
@@ -46,17 +28,62 @@ impl History {
     }
 }
 
-pub fn try_builtin(_line: &str, tokens: &[String], jobs: &mut JobTable, hist: &mut History) -> BuiltinResult {
+pub fn is_builtin(name: &str) -> bool {
+    matches!(
+        name,
+        "exit" | "cd" | "jobs" | "set" | "shopt" | "shift" | "export" | "readonly" | "unset" | "declare" | "typeset"
+            | "break" | "continue" | "read" | "return" | "local" | ":"
+    )
+}
+
+/// `arrays` holds, at the same index as each `name=` token, the items a
+/// declaration builtin's `name=(...)` argument assigns; it is empty for
+/// other commands.
+pub fn try_builtin(_line: &str, tokens: &[String], arrays: &[ArrayArg], shell: &mut Shell) -> BuiltinResult {
     if tokens.is_empty() { return BuiltinResult::NotHandled; }
+    let arrays = arrays.get(1..).unwrap_or_default();
     match tokens[0].as_str() {
-        "exit" => { builtin_exit(jobs, hist); /* never returns */ }
-        "cd"   => { builtin_cd(&tokens[1..]); return BuiltinResult::Handled; }
-        "jobs" => { builtin_jobs(jobs); return BuiltinResult::Handled; }
+        "exit" => {
+            // exit [n]: without n, the status of the last command
+            let status = match tokens.get(1).map(|arg| arg.parse::<i32>()) {
+                None => shell.last_status,
+                Some(Ok(status)) => status & 0xff,
+                Some(Err(_)) => {
+                    eprintln!("exit: {}: numeric argument required", tokens[1]);
+                    2
+                }
+            };
+            exit_shell(shell, status);
+        }
+        "cd"   => BuiltinResult::Handled(builtin_cd(&tokens[1..], shell)),
+        "jobs" => { builtin_jobs(&shell.jobs); BuiltinResult::Handled(0) }
+        "set"  => BuiltinResult::Handled(builtin_set(&tokens[1..], shell)),
+        "shift" => BuiltinResult::Handled(builtin_shift(&tokens[1..], shell)),
+        "shopt" => BuiltinResult::Handled(builtin_shopt(&tokens[1..], &mut shell.options)),
+        "export" => BuiltinResult::Handled(builtin_export(&tokens[1..], arrays, shell)),
+        "readonly" => BuiltinResult::Handled(builtin_readonly(&tokens[1..], arrays, shell)),
+        "unset" => BuiltinResult::Handled(builtin_unset(&tokens[1..], shell)),
+        "declare" | "typeset" => BuiltinResult::Handled(builtin_declare(&tokens[0], &tokens[1..], arrays, shell)),
+        "break" | "continue" => BuiltinResult::Handled(builtin_control(&tokens[0], &tokens[1..], shell)),
+        "read" => BuiltinResult::Handled(builtin_read(&tokens[1..], shell)),
+        "return" => BuiltinResult::Handled(builtin_return(&tokens[1..], shell)),
+        "local" => BuiltinResult::Handled(builtin_local(&tokens[1..], arrays, shell)),
+        // `:` does nothing but expand its arguments
+        ":" => BuiltinResult::Handled(0),
         _ => BuiltinResult::NotHandled,
     }
 }
 
-fn builtin_exit(jobs: &mut JobTable, hist: &History) -> ! {
+/// Leave the shell with `status`. A script, -c command or subshell just
+/// ends; the session summary belongs to a main shell reading stdin.
+pub fn exit_shell(shell: &mut Shell, status: i32) -> ! {
+    if !shell.from_stdin || std::process::id() as i32 != shell.pid {
+        std::process::exit(status);
+    }
+    builtin_exit(&mut shell.jobs, &shell.history, status)
+}
+
+pub fn builtin_exit(jobs: &mut JobTable, hist: &History, status: i32) -> ! {
     // Wait for all running background jobs
     while jobs.has_active() {
         jobs.poll();
@@ -81,33 +108,716 @@ fn builtin_exit(jobs: &mut JobTable, hist: &History) -> ! {
             }
         }
     }
-    std::process::exit(0);
+    std::process::exit(status);
 }
 
-fn builtin_cd(args: &[String]) {
-    match args.len() {
-        0 => {
-            let home = env::var("HOME").unwrap_or_else(|_| String::from("/"));
-            if let Err(e) = env::set_current_dir(&home) {
-                eprintln!("cd: {}", e);
+// The argument has already been through word expansion, so `~` and `$VAR`
+// are resolved the same way as for any other command. `cd -` goes back to
+// $OLDPWD.
+fn builtin_cd(args: &[String], shell: &mut Shell) -> i32 {
+    let target = match args {
+        [] => shell.get_var("HOME").unwrap_or_else(|| String::from("/")),
+        [arg] if arg == "-" => match shell.get_var("OLDPWD") {
+            Some(dir) => {
+                println!("{}", dir);
+                dir
             }
-        }
-        1 => {
-            let target = expand_cd_arg(&args[0]);
-            if let Err(e) = env::set_current_dir(&target) {
-                eprintln!("cd: {}", e);
+            None => {
+                eprintln!("cd: OLDPWD not set");
+                return 1;
             }
-        }
+        },
+        [arg] => arg.clone(),
         _ => {
             eprintln!("cd: too many arguments");
+            return 1;
         }
+    };
+    let previous = env::current_dir().ok();
+    if let Err(e) = env::set_current_dir(&target) {
+        eprintln!("cd: {}", e);
+        return 1;
+    }
+    // keep $PWD and $OLDPWD current for ~+ and ~-
+    if let Some(previous) = previous {
+        let _ = shell.set_var("OLDPWD", &previous.to_string_lossy());
+    }
+    if let Ok(current) = env::current_dir() {
+        let _ = shell.set_var("PWD", &current.to_string_lossy());
     }
+    0
 }
 
 fn builtin_jobs(jobs: &JobTable) {
     jobs.list_running();
 }
 
+// set -o name / set +o name turn an option on / off; -f is short for noglob.
+// A bare -o or +o lists the options. Arguments after `--`, or from the first
+// one that isn't an option, replace the positional parameters.
+fn builtin_set(args: &[String], shell: &mut Shell) -> i32 {
+    let options = &mut shell.options;
+    let mut i = 0;
+    while i < args.len() {
+        let (on, flag) = match args[i].split_at_checked(1) {
+            _ if args[i] == "--" => {
+                shell.positional = args[i + 1..].to_vec();
+                return 0;
+            }
+            Some(("-", flag)) => (true, flag),
+            Some(("+", flag)) => (false, flag),
+            _ => {
+                shell.positional = args[i..].to_vec();
+                return 0;
+            }
+        };
+        match flag {
+            "o" => match args.get(i + 1) {
+                Some(name) => {
+                    match options.get_mut(name) {
+                        Some(option) => *option = on,
+                        None => {
+                            eprintln!("set: {}: invalid option name", name);
+                            return 2;
+                        }
+                    }
+                    i += 1;
+                }
+                None => print_options(options, on),
+            },
+            "f" => options.noglob = on,
+            _ => {
+                eprintln!("set: {}: invalid option", args[i]);
+                return 2;
+            }
+        }
+        i += 1;
+    }
+    0
+}
+
+// break [n] / continue [n]: leave, or go on to the next iteration of, the
+// nth enclosing loop. The executor unwinds to it; n past the outermost loop
+// means the outermost.
+fn builtin_control(cmd: &str, args: &[String], shell: &mut Shell) -> i32 {
+    let count = match args.first().map(|n| n.parse::<usize>()) {
+        None => 1,
+        Some(Ok(count)) if count > 0 => count,
+        Some(_) => {
+            eprintln!("{}: {}: loop count out of range", cmd, args[0]);
+            return 1;
+        }
+    };
+    if shell.loop_depth == 0 {
+        eprintln!("{}: only meaningful in a `for', `while', or `until' loop", cmd);
+        return 0;
+    }
+    let count = count.min(shell.loop_depth);
+    shell.control = Some(if cmd == "break" { Control::Break(count) } else { Control::Continue(count) });
+    0
+}
+
+// return [n]: leave the running function with status n, by default the
+// status of the last command
+fn builtin_return(args: &[String], shell: &mut Shell) -> i32 {
+    if shell.locals.is_empty() {
+        eprintln!("return: can only `return' from a function");
+        return 1;
+    }
+    let status = match args.first().map(|n| n.parse::<i32>()) {
+        None => shell.last_status,
+        Some(Ok(status)) => status & 0xff,
+        Some(Err(_)) => {
+            eprintln!("return: {}: numeric argument required", args[0]);
+            2
+        }
+    };
+    shell.control = Some(Control::Return);
+    status
+}
+
+// local [-aAilrux] name[=value]...: like declare, but the variables only
+// last until the running function returns. The outer variable of the same
+// name is hidden until then, not modified.
+fn builtin_local(args: &[String], arrays: &[ArrayArg], shell: &mut Shell) -> i32 {
+    if shell.locals.is_empty() {
+        eprintln!("local: can only be used in a function");
+        return 1;
+    }
+    let (on, off, other, names) = match parse_attr_flags("local", args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let arrays = operand_arrays(args, names, arrays);
+    let mut status = 0;
+    let mut declared = Vec::new();
+    let mut declared_arrays = Vec::new();
+    for (i, arg) in names.iter().enumerate() {
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        if shell.vars.get(name).is_some_and(|var| var.attrs.readonly) {
+            eprintln!("local: {}: readonly variable", name);
+            status = 1;
+            continue;
+        }
+        let frame = shell.locals.last().expect("checked above");
+        if is_name(name) && !frame.iter().any(|(saved, _)| saved == name) {
+            let outer = shell.vars.replace(name, None);
+            shell.locals.last_mut().expect("checked above").push((name.to_string(), outer));
+        }
+        declared.push(arg.clone());
+        declared_arrays.push(arrays.get(i).cloned().flatten());
+    }
+    let array = ['A', 'a'].into_iter().find(|&kind| other.contains(kind));
+    declare_names("local", &declared, &declared_arrays, on, off, array, shell).max(status)
+}
+
+// read [-r] [name...]: read a line from stdin and split it on $IFS, one field
+// per name and the rest of the line in the last one; without names the whole
+// line goes in REPLY. Without -r a backslash escapes the next character and
+// backslash-newline continues the line. Fails at the end of input.
+fn builtin_read(args: &[String], shell: &mut Shell) -> i32 {
+    let (raw, names) = match args.first().map(String::as_str) {
+        Some("-r") => (true, &args[1..]),
+        Some(flag) if flag.starts_with('-') && flag != "-" => {
+            eprintln!("read: {}: invalid option", flag);
+            eprintln!("read: usage: read [-r] [name ...]");
+            return 2;
+        }
+        _ => (false, args),
+    };
+    if let Some(name) = names.iter().find(|name| !is_name(name)) {
+        eprintln!("read: `{}': not a valid identifier", name);
+        return 1;
+    }
+    let (line, complete) = read_line_from_stdin(raw);
+    if line.is_empty() && !complete {
+        return 1;
+    }
+
+    let ifs = shell.get_var("IFS").unwrap_or_else(|| String::from(" \t\n"));
+    let separator = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let space = |&(c, escaped): &(char, bool)| !escaped && c.is_whitespace() && ifs.contains(c);
+    let text = |chars: &[(char, bool)]| chars.iter().map(|(c, _)| c).collect::<String>();
+
+    let assignments: Vec<(&str, String)> = if names.is_empty() {
+        vec![("REPLY", text(&line))]
+    } else {
+        let mut rest = &line[..];
+        let mut assignments = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            rest = &rest[rest.iter().take_while(|c| space(c)).count()..];
+            if i == names.len() - 1 {
+                let end = rest.len() - rest.iter().rev().take_while(|c| space(c)).count();
+                assignments.push((name.as_str(), text(&rest[..end])));
+                break;
+            }
+            let end = rest.iter().position(separator).unwrap_or(rest.len());
+            assignments.push((name.as_str(), text(&rest[..end])));
+            // the separator: IFS whitespace around at most one other IFS character
+            rest = &rest[end..];
+            rest = &rest[rest.iter().take_while(|c| space(c)).count()..];
+            if rest.first().is_some_and(|c| separator(c) && !space(c)) {
+                rest = &rest[1..];
+            }
+        }
+        assignments
+    };
+    for (name, value) in assignments {
+        if let Err(message) = shell.set_var(name, &value) {
+            eprintln!("read: {}", message);
+            return 1;
+        }
+    }
+    if complete { 0 } else { 1 }
+}
+
+// Read one line from fd 0 a byte at a time, so nothing past the newline is
+// taken from a pipe or file that later commands read. Returns the characters,
+// each flagged if it was backslash-escaped, and whether a newline ended it.
+fn read_line_from_stdin(raw: bool) -> (Vec<(char, bool)>, bool) {
+    let mut bytes = Vec::new();
+    let mut escaped = Vec::new();
+    let mut pending_escape = false;
+    let complete = loop {
+        let mut byte = 0u8;
+        let n = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        if n <= 0 {
+            break false;
+        }
+        match byte {
+            b'\\' if !raw && !pending_escape => pending_escape = true,
+            b'\n' if pending_escape => pending_escape = false,
+            b'\n' => break true,
+            _ => {
+                bytes.push(byte);
+                escaped.push(pending_escape);
+                pending_escape = false;
+            }
+        }
+    };
+    // decode, keeping the escape flag of the first byte of each character
+    let text = String::from_utf8_lossy(&bytes);
+    let mut offset = 0;
+    let chars = text
+        .chars()
+        .map(|c| {
+            let flag = escaped.get(offset).copied().unwrap_or(false);
+            offset += c.len_utf8();
+            (c, flag)
+        })
+        .collect();
+    (chars, complete)
+}
+
+// shift [n]: drop the first n positional parameters (default 1)
+fn builtin_shift(args: &[String], shell: &mut Shell) -> i32 {
+    let count = match args.first().map(|n| n.parse::<usize>()) {
+        None => 1,
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            eprintln!("shift: {}: numeric argument required", args[0]);
+            return 2;
+        }
+    };
+    if count > shell.positional.len() {
+        eprintln!("shift: {}: shift count out of range", count);
+        return 1;
+    }
+    shell.positional.drain(..count);
+    0
+}
+
+// shopt -s name... / shopt -u name... set / unset options; shopt name...
+// reports them and fails if any is off.
+fn builtin_shopt(args: &[String], options: &mut Options) -> i32 {
+    let (set, names) = match args.first().map(String::as_str) {
+        Some("-s") => (Some(true), &args[1..]),
+        Some("-u") => (Some(false), &args[1..]),
+        _ => (None, args),
+    };
+    if names.is_empty() {
+        match set {
+            Some(on) => {
+                for name in Options::NAMES {
+                    if options.get(name) == Some(on) {
+                        println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+                    }
+                }
+            }
+            None => print_options(options, true),
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for name in names {
+        let option = match options.get_mut(name) {
+            Some(option) => option,
+            None => {
+                eprintln!("shopt: {}: invalid shell option name", name);
+                status = 1;
+                continue;
+            }
+        };
+        match set {
+            Some(on) => *option = on,
+            None => {
+                println!("{:<15}\t{}", name, if *option { "on" } else { "off" });
+                if !*option { status = 1; }
+            }
+        }
+    }
+    status
+}
+
+// Split flags like `-xr` / `+x` off the front of args. Returns the attributes
+// to turn on, the ones to turn off, the other letters seen (a, A, p, f, n...)
+// and the remaining operands.
+fn parse_attr_flags<'a>(cmd: &str, args: &'a [String]) -> Result<(Attrs, Attrs, String, &'a [String]), i32> {
+    let (mut on, mut off, mut other) = (Attrs::default(), Attrs::default(), String::new());
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        if arg == "--" {
+            i += 1;
+            break;
+        }
+        let (set, letters) = match arg.split_at_checked(1) {
+            Some(("-", letters)) if !letters.is_empty() => (true, letters),
+            Some(("+", letters)) if !letters.is_empty() => (false, letters),
+            _ => break,
+        };
+        for letter in letters.chars() {
+            let attrs = if set { &mut on } else { &mut off };
+            match letter {
+                'x' => attrs.exported = true,
+                'r' => attrs.readonly = true,
+                'i' => attrs.integer = true,
+                'l' => attrs.lowercase = true,
+                'u' => attrs.uppercase = true,
+                'a' | 'A' | 'p' | 'f' | 'F' | 'n' if set => other.push(letter),
+                _ => {
+                    eprintln!("{}: {}{}: invalid option", cmd, if set { '-' } else { '+' }, letter);
+                    return Err(2);
+                }
+            }
+        }
+        i += 1;
+    }
+    if off.readonly {
+        eprintln!("{}: +r: can't remove the readonly attribute", cmd);
+        return Err(2);
+    }
+    Ok((on, off, other, &args[i..]))
+}
+
+// Apply attributes and an optional value to each `NAME[=value]` operand.
+// `array` is 'a' or 'A' to make the names indexed or associative arrays; an
+// operand written `NAME=(...)` assigns the items at its index in `arrays`.
+fn declare_names(
+    cmd: &str,
+    names: &[String],
+    arrays: &[ArrayArg],
+    on: Attrs,
+    off: Attrs,
+    array: Option<char>,
+    shell: &mut Shell,
+) -> i32 {
+    let mut status = 0;
+    for (i, arg) in names.iter().enumerate() {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !is_name(name) {
+            eprintln!("{}: `{}': not a valid identifier", cmd, arg);
+            status = 1;
+            continue;
+        }
+        let var = shell.vars.get_or_create(name);
+        match (array, var.value.take()) {
+            (Some('A'), Some(Value::Indexed(map))) if !map.is_empty() => {
+                var.value = Some(Value::Indexed(map));
+                eprintln!("{}: {}: cannot convert indexed to associative array", cmd, name);
+                status = 1;
+                continue;
+            }
+            (Some('A'), Some(Value::Scalar(old))) => var.value = Some(Value::Assoc(BTreeMap::from([(String::from("0"), old)]))),
+            (Some('A'), None | Some(Value::Indexed(_))) => var.value = Some(Value::Assoc(BTreeMap::new())),
+            (Some(_), Some(Value::Scalar(old))) => var.value = Some(Value::Indexed(BTreeMap::from([(0, old)]))),
+            (Some(_), None) => var.value = Some(Value::Indexed(BTreeMap::new())),
+            (_, value) => var.value = value,
+        }
+        let attrs = &mut var.attrs;
+        attrs.exported = (attrs.exported || on.exported) && !off.exported;
+        attrs.integer = (attrs.integer || on.integer) && !off.integer;
+        // -l and -u replace each other
+        if on.lowercase || on.uppercase {
+            attrs.lowercase = on.lowercase;
+            attrs.uppercase = on.uppercase;
+        }
+        attrs.lowercase &= !off.lowercase;
+        attrs.uppercase &= !off.uppercase;
+
+        let assigned = match (arrays.get(i).cloned().flatten(), value) {
+            (Some(items), _) => shell.assign_array(name, items, false),
+            (None, Some(value)) => shell.set_var(name, value),
+            (None, None) => Ok(()),
+        };
+        if let Err(message) = assigned {
+            eprintln!("{}: {}", cmd, message);
+            status = 1;
+            continue;
+        }
+        if on.readonly {
+            shell.vars.get_or_create(name).attrs.readonly = true;
+        }
+    }
+    status
+}
+
+// A variable as a `declare` command that would recreate it
+fn print_declaration(name: &str, var: &Var) {
+    match &var.value {
+        Some(value) => println!("declare {} {}={}", var.flags(), name, format_value(value)),
+        None => println!("declare {} {}", var.flags(), name),
+    }
+}
+
+// A value as it appears in declare output: "text" or ([key]="text" ...)
+fn format_value(value: &Value) -> String {
+    let items: Vec<String> = match value {
+        Value::Scalar(value) => return format!("\"{}\"", escape_double_quoted(value)),
+        Value::Indexed(map) => map.iter().map(|(k, v)| format!("[{}]=\"{}\"", k, escape_double_quoted(v))).collect(),
+        Value::Assoc(map) => map.iter().map(|(k, v)| format!("[{}]=\"{}\"", k, escape_double_quoted(v))).collect(),
+    };
+    format!("({})", items.join(" "))
+}
+
+fn escape_double_quoted(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// The part of `arrays` (which lines up with `args`) for the operands
+// `names` left at the end of `args` once the flags are parsed
+fn operand_arrays<'a>(args: &[String], names: &[String], arrays: &'a [ArrayArg]) -> &'a [ArrayArg] {
+    arrays.get(args.len() - names.len()..).unwrap_or_default()
+}
+
+// Whether every attribute in `wanted` is set in `attrs`
+fn has_attrs(attrs: Attrs, wanted: Attrs) -> bool {
+    (!wanted.exported || attrs.exported)
+        && (!wanted.readonly || attrs.readonly)
+        && (!wanted.integer || attrs.integer)
+        && (!wanted.lowercase || attrs.lowercase)
+        && (!wanted.uppercase || attrs.uppercase)
+}
+
+// declare / typeset [-aAixlur] [-p] [name[=value]...]. Without names it lists
+// variables: all of them as NAME=value, or as declare commands with -p or
+// an attribute filter.
+fn builtin_declare(cmd: &str, args: &[String], arrays: &[ArrayArg], shell: &mut Shell) -> i32 {
+    let (on, off, other, names) = match parse_attr_flags(cmd, args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    if other.contains(['f', 'F']) {
+        return print_functions(names, other.contains('F'), shell);
+    }
+    if other.contains('p') && !names.is_empty() {
+        let mut status = 0;
+        for name in names {
+            match shell.vars.get(name) {
+                Some(var) => print_declaration(name, var),
+                None => {
+                    eprintln!("{}: {}: not found", cmd, name);
+                    status = 1;
+                }
+            }
+        }
+        return status;
+    }
+    let array = if other.contains('A') { Some('A') } else if other.contains('a') { Some('a') } else { None };
+    if names.is_empty() {
+        let declarations = other.contains('p') || on != Attrs::default() || array.is_some();
+        for (name, var) in shell.vars.sorted() {
+            let kind_matches = match array {
+                Some('A') => matches!(var.value, Some(Value::Assoc(_))),
+                Some(_) => matches!(var.value, Some(Value::Indexed(_))),
+                None => true,
+            };
+            if !has_attrs(var.attrs, on) || !kind_matches {
+                continue;
+            }
+            if declarations {
+                print_declaration(name, var);
+            } else if let Some(value) = &var.value {
+                println!("{}={}", name, format_value(value));
+            }
+        }
+        return 0;
+    }
+    declare_names(cmd, names, operand_arrays(args, names, arrays), on, off, array, shell)
+}
+
+// export [-n] [-p] [name[=value]...]: -n stops exporting the names
+fn builtin_export(args: &[String], arrays: &[ArrayArg], shell: &mut Shell) -> i32 {
+    let (_, _, other, names) = match parse_attr_flags("export", args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    let exported = Attrs { exported: true, ..Attrs::default() };
+    if names.is_empty() {
+        for (name, var) in shell.vars.sorted() {
+            if var.attrs.exported {
+                print_declaration(name, var);
+            }
+        }
+        return 0;
+    }
+    let arrays = operand_arrays(args, names, arrays);
+    if other.contains('n') {
+        declare_names("export", names, arrays, Attrs::default(), exported, None, shell)
+    } else {
+        declare_names("export", names, arrays, exported, Attrs::default(), None, shell)
+    }
+}
+
+// readonly [-p] [name[=value]...]
+fn builtin_readonly(args: &[String], arrays: &[ArrayArg], shell: &mut Shell) -> i32 {
+    let (_, _, other, names) = match parse_attr_flags("readonly", args) {
+        Ok(parsed) => parsed,
+        Err(status) => return status,
+    };
+    if names.is_empty() {
+        for (name, var) in shell.vars.sorted() {
+            if var.attrs.readonly {
+                print_declaration(name, var);
+            }
+        }
+        return 0;
+    }
+    let readonly = Attrs { readonly: true, ..Attrs::default() };
+    let array = ['A', 'a'].into_iter().find(|&kind| other.contains(kind));
+    declare_names("readonly", names, operand_arrays(args, names, arrays), readonly, Attrs::default(), array, shell)
+}
+
+// declare -f [name...] prints function definitions, declare -F just their
+// names. Fails if a named function doesn't exist.
+fn print_functions(names: &[String], names_only: bool, shell: &Shell) -> i32 {
+    let mut status = 0;
+    let functions: Vec<_> = if names.is_empty() {
+        shell.functions.values().collect()
+    } else {
+        names
+            .iter()
+            .filter_map(|name| {
+                let function = shell.functions.get(name);
+                if function.is_none() {
+                    status = 1;
+                }
+                function
+            })
+            .collect()
+    };
+    for function in functions {
+        if names_only {
+            println!("declare -f {}", function.name);
+        } else {
+            println!("{}", function.text);
+        }
+    }
+    status
+}
+
+// unset [-v|-f] name...: -v removes variables, -f functions. Without either
+// a name that isn't a variable is removed as a function. `name[subscript]`
+// removes one array element.
+fn builtin_unset(args: &[String], shell: &mut Shell) -> i32 {
+    let (kind, names) = match args.first().map(String::as_str) {
+        Some("-f") => (Some('f'), &args[1..]),
+        Some("-v") => (Some('v'), &args[1..]),
+        _ => (None, args),
+    };
+    if kind == Some('f') {
+        for name in names {
+            shell.functions.remove(name);
+        }
+        return 0;
+    }
+    let mut status = 0;
+    for name in names {
+        if let Some((array, subscript)) = name.strip_suffix(']').and_then(|n| n.split_once('[')) {
+            if let Err(message) = shell.unset_element(array, subscript) {
+                eprintln!("unset: {}", message);
+                status = 1;
+            }
+            continue;
+        }
+        if shell.vars.get(name).is_some_and(|var| var.attrs.readonly) {
+            eprintln!("unset: {}: cannot unset: readonly variable", name);
+            status = 1;
+            continue;
+        }
+        if shell.vars.replace(name, None).is_none() && kind.is_none() {
+            shell.functions.remove(name);
+        }
+    }
+    status
+}
+
+// `set -o` lists options as a table, `set +o` as commands that restore them
+fn print_options(options: &Options, table: bool) {
+    for name in Options::NAMES {
+        let on = options.get(name) == Some(true);
+        if table {
+            println!("{:<15}\t{}", name, if on { "on" } else { "off" });
+        } else {
+            println!("set {}o {}", if on { '-' } else { '+' }, name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str], shell: &mut Shell) -> i32 {
+        let tokens: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match try_builtin("", &tokens, &[], shell) {
+            BuiltinResult::Handled(status) => status,
+            BuiltinResult::NotHandled => panic!("{} is not a builtin", args[0]),
+        }
+    }
+
+    #[test]
+    fn declare_attributes() {
+        let mut shell = Shell::new();
+        assert_eq!(run(&["declare", "-i", "bt_n=2+3"], &mut shell), 0);
+        assert_eq!(shell.get_var("bt_n").as_deref(), Some("5"));
+        shell.set_var("bt_n", "bt_n * 2").unwrap();
+        assert_eq!(shell.get_var("bt_n").as_deref(), Some("10"));
+        run(&["declare", "+i", "bt_n"], &mut shell);
+        shell.set_var("bt_n", "1+1").unwrap();
+        assert_eq!(shell.get_var("bt_n").as_deref(), Some("1+1"));
+
+        run(&["typeset", "-l", "bt_s=MiXed"], &mut shell);
+        assert_eq!(shell.get_var("bt_s").as_deref(), Some("mixed"));
+        // -u replaces -l
+        run(&["declare", "-u", "bt_s"], &mut shell);
+        shell.set_var("bt_s", "MiXed").unwrap();
+        assert_eq!(shell.get_var("bt_s").as_deref(), Some("MIXED"));
+
+        assert_eq!(run(&["declare", "1x=2"], &mut shell), 1);
+        assert_eq!(run(&["declare", "-q", "bt_q"], &mut shell), 2);
+    }
+
+    #[test]
+    fn readonly_and_export() {
+        let mut shell = Shell::new();
+        assert_eq!(run(&["readonly", "bt_r=1"], &mut shell), 0);
+        assert!(shell.set_var("bt_r", "2").is_err());
+        assert_eq!(run(&["declare", "bt_r=2"], &mut shell), 1);
+        assert_eq!(run(&["unset", "bt_r"], &mut shell), 1);
+        assert_eq!(run(&["declare", "+r", "bt_r"], &mut shell), 2);
+        assert_eq!(shell.get_var("bt_r").as_deref(), Some("1"));
+
+        run(&["export", "bt_x=1"], &mut shell);
+        assert!(shell.vars.get("bt_x").unwrap().attrs.exported);
+        run(&["export", "-n", "bt_x"], &mut shell);
+        let var = shell.vars.get("bt_x").unwrap();
+        assert!(!var.attrs.exported);
+        assert_eq!(shell.get_var("bt_x").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn shift_and_set_positional() {
+        let mut shell = Shell::new();
+        assert_eq!(run(&["set", "--", "a", "b", "c"], &mut shell), 0);
+        assert_eq!(shell.positional, ["a", "b", "c"]);
+        assert_eq!(run(&["shift"], &mut shell), 0);
+        assert_eq!(shell.positional, ["b", "c"]);
+        assert_eq!(run(&["shift", "3"], &mut shell), 1);
+        assert_eq!(run(&["shift", "x"], &mut shell), 2);
+        assert_eq!(shell.positional, ["b", "c"]);
+        assert_eq!(run(&["shift", "2"], &mut shell), 0);
+        assert!(shell.positional.is_empty());
+
+        // options come before the operands; -- alone clears them
+        run(&["set", "-f", "x", "-y"], &mut shell);
+        assert!(shell.options.noglob);
+        assert_eq!(shell.positional, ["x", "-y"]);
+        run(&["set", "--"], &mut shell);
+        assert!(shell.positional.is_empty());
+    }
+}
+
 
 
 
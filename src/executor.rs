@@ -1,135 +1,23 @@
-use std::{ffi::CString, ptr};
-use libc::{close, dup2, open, fstat, stat as stat_t, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY, STDIN_FILENO, STDOUT_FILENO, S_IFMT, S_IFREG, ENOENT};
-use nix::libc::{execv, fork, waitpid, pipe};
-use crate::parser::{expand_tokens, tokenize};
+use std::{ffi::CString, fs::File, rc::Rc, io::{stdout, Read, Write}, os::{fd::{FromRawFd, IntoRawFd}, unix::ffi::OsStringExt}, ptr};
+use libc::{c_char, close, dup2, fcntl, lseek, mkstemp, open, unlink, SEEK_SET, fstat, stat as stat_t, F_DUPFD_CLOEXEC, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, STDIN_FILENO, STDOUT_FILENO, S_IFMT, S_IFREG, ENOENT, ENOEXEC, WEXITSTATUS, WIFEXITED, WIFSIGNALED, WTERMSIG};
+use nix::libc::{execve, fork, waitpid, pipe};
+use crate::ast::{AndOr, ArithForCommand, Assignment, CaseCommand, CaseTerminator, Command, CompoundCommand, Connector, ForCommand, FunctionDef, IfCommand, List, ListItem, Pipeline, RedirKind, Redirect, SimpleCommand, WhileCommand, Word, WordPart};
+use crate::builtins::{is_builtin, try_builtin, ArrayArg, BuiltinResult};
+use crate::expand::{expand_arith, expand_array_items, expand_pattern, expand_word, expand_words, ExpandError};
+use crate::glob::pattern_matches;
 use crate::job::JobTable;
+use crate::parser::{parse, resolve_path, split_assignment};
+use crate::shell::{Control, Shell};
+use crate::vars::Var;
 
+// How deeply functions can call each other unless $FUNCNEST says otherwise.
+// Each call nests the executor a few times over on the shell's own stack, so
+// this stays well short of overflowing it even for deeply nested bodies.
+const MAX_FUNCTION_DEPTH: usize = 256;
 
-struct CommandPart {
-    program: CString,
-    args: Vec<CString>,
-    redir_in: Option<String>,   // e.g. < input.txt
-    redir_out: Option<String>,  // e.g. > output.txt
-    direction: Option<Direction>, // still keep this for pipes or future chaining
-    background: bool,
-    parse_error: Option<&'static str>,  //for redirection issues
-}
-
-enum Direction {
-    Pipe,
-    // RedirOut(String), // file name
-    // RedirIn(String),  // file name
-    // maybe in future: AndThen, OrElse, Sequence
-    
-}
-
-impl PartialEq for Direction {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            // Compare only the discriminant (variant name) for Pipe
-            (Direction::Pipe, Direction::Pipe) => true,
-            // Compare RedirOut and RedirIn by their inner String values
-            // (Direction::RedirOut(s1), Direction::RedirOut(s2)) => s1 == s2,
-            // (Direction::RedirIn(s1), Direction::RedirIn(s2)) => s1 == s2,
-            // All other combinations are not equal
-            // _ => false,
-        }
-    }
-}
-
-// pub fn execute_command(command: &str) {
-//     // Phase 1: Tokenization and Expansion
-//     let tokens: Vec<_> = tokenize(command);
-//     let expanded_tokens: Vec<CString> = expand_tokens(tokens);
-
-//     // Phase 2: Interpretation and Execution
-//     let commands: Vec<CommandPart> = interpret_tokens(expanded_tokens);
-//     execute(commands);
-// }
-
-fn interpret_tokens(tokens: Vec<CString>) -> Vec<CommandPart> {
-    let mut command_parts: Vec<CommandPart> = Vec::new();
-    let mut current_part: Option<CommandPart> = None;
-
-    let mut tokens_iter = tokens.iter().peekable();
-    while let Some(t) = tokens_iter.next() {
-        // The first token is always the program
-        // The rest are arguments until I hit a special token
-
-        if current_part.is_none() {
-            current_part = Some(CommandPart {
-                program: t.clone(),
-                args: Vec::new(),
-                redir_in: None,
-                redir_out: None,
-                direction: None,
-                background: false,
-                parse_error: None,
-            });
-        } else {
-            current_part.as_mut().unwrap().args.push(t.clone());
-        }
-
-        if t.to_str().unwrap() == "|" {
-            current_part.as_mut().unwrap().args.pop();
-            current_part.as_mut().unwrap().direction = Some(Direction::Pipe);
-            command_parts.push(current_part.take().unwrap());
-            current_part = None;
-        } else if t.to_str().unwrap() == ">" {
-            current_part.as_mut().unwrap().args.pop(); // remove ">" from args
-            if let Some(next_token) = tokens_iter.next() {
-                let filename = next_token.to_str().unwrap().to_string();
-                current_part.as_mut().unwrap().redir_out = Some(filename);
-            } else {
-                current_part.as_mut().unwrap().parse_error = Some("missing output file after '>'");
-            }
-        } else if t.to_str().unwrap() == "<" {
-            current_part.as_mut().unwrap().args.pop(); // remove "<" from args
-            if let Some(next_token) = tokens_iter.next() {
-                let filename = next_token.to_str().unwrap().to_string();
-                current_part.as_mut().unwrap().redir_in = Some(filename);
-            } else {
-                current_part.as_mut().unwrap().parse_error = Some("missing input file after '<'");
-            }
-        } else if t.to_str().unwrap() == "&" {
-            current_part.as_mut().unwrap().background = true;
-            current_part.as_mut().unwrap().args.pop();
-            command_parts.push(current_part.take().unwrap());
-            current_part = None;
-        } else {
-            if t == tokens.last().unwrap() && current_part.is_some() {
-                command_parts.push(current_part.take().unwrap());
-            }
-        }
-    }
-    if let Some(final_part) = current_part.take() {
-            command_parts.push(final_part);
-        }
-    
-    // For debugging purposes
-    // println!("Interpreted Command Parts: ");
-    // for part in command_parts.iter() {
-    //     println!("Program: {}", part.program.to_str().unwrap());
-    //     for arg in part.args.iter() {
-    //         println!("Arg: {}", arg.to_str().unwrap());
-    //     }
-    //     if let Some(dir) = &part.direction {
-    //         match dir {
-    //             Direction::Pipe => println!("Direction: Pipe"),
-    //         }
-    //     } else {
-    //         println!("Direction: None");
-    //     }
-    //     if let Some(redir_out) = &part.redir_out {
-    //         println!("Redir Out: {}", redir_out);
-    //     }
-    //     if let Some(redir_in) = &part.redir_in {
-    //         println!("Redir In: {}", redir_in);
-    //     }
-    //     println!("Background: {}", part.background);
-    // }
-    command_parts
-}
+// Builtins whose `name=(...)` arguments assign arrays, unless a function
+// of the same name hides them
+const DECLARATION_BUILTINS: &[&str] = &["declare", "typeset", "local", "export", "readonly"];
 
 //verify that fd refers to regular file
 fn is_regular_fd(fd: i32) -> bool {
@@ -138,319 +26,791 @@ fn is_regular_fd(fd: i32) -> bool {
         if fstat(fd, &mut st) !=0 {
             return false;
         }
-        ((st.st_mode as u32) & (S_IFMT as u32)) == (S_IFREG as u32)
-    }
-}
-
-// fn execute(command_parts: Vec<CommandPart>) {
-
-//     // If there's only one command part, execute it normally
-//     if command_parts.len() == 1 {
-//         unsafe {
-//             let cmd = &command_parts[0];
-//             //bail early on parse error such as missing file name
-//             if let Some(msg) = cmd.parse_error { eprintln!("redirection error: {}", msg); return; }
-//             let pid: i32 = fork();
-//             if pid < 0 {
-//                 eprintln!("Fork failed!");
-//             } else if pid == 0 { // Child process
-
-//                 // added this
-//                 if let Some(filename) = &cmd.redir_out {
-//                     let file = CString::new(filename.clone()).unwrap();
-//                     //mode 0600
-//                     let fd = open(file.as_ptr(), O_WRONLY | O_CREAT | O_TRUNC, 0o600);
-//                     if fd == -1 {
-//                         eprintln!("open failed for output '{}'", filename);
-//                         std::process::exit(1);
-//                     }
-//                     dup2(fd, STDOUT_FILENO);
-//                     close(fd);
-//                 }
-
-//                 if let Some(filename) = &cmd.redir_in {
-//                     let file = CString::new(filename.clone()).unwrap();
-//                     let fd = open(file.as_ptr(), O_RDONLY);
-//                     if fd == -1 {
-//                         eprintln!("input file not found '{}'", filename);
-//                         std::process::exit(1);
-//                     }
-//                     //make sure regular file
-//                     if !is_regular_fd(fd) {
-//                         eprintln!("input is not a regular file '{}'", filename);
-//                         close(fd);
-//                         std::process::exit(1);
-//                     }
-//                     dup2(fd, STDIN_FILENO);
-//                     close(fd);
-//                 }
-
-//                 // Build argv
-//                 let mut argv = vec![cmd.program.as_ptr()];
-//                 for arg in cmd.args.iter() {
-//                     argv.push(arg.as_ptr());
-//                 }
-//                 argv.push(ptr::null());
-
-//                 execv(cmd.program.as_ptr(), argv.as_ptr());
-
-//                 //changed exec failure messages
-//                 let err = std::io::Error::last_os_error();
-//                 if let Some(code) = err.raw_os_error() {
-//                     if code == ENOENT {
-//                         eprintln!("command not found");
-//                     } else {
-//                         eprintln!("exec failed: {}", err);
-//                     }
-//                 } else {
-//                     eprintln!("exec failed");
-//                 }
-//                 std::process::exit(127);
-//             } else {
-//                 if !cmd.background {
-//                     waitpid(pid, ptr::null_mut(), 0);
-//                 }
-//             }
-//         }
-//     } else {
-//         let mut previous_fd: Option<i32> = None; // the read-end of the previous pipe
-
-//         for part in command_parts.iter() {
-//             if let Some(msg) = part.parse_error { eprintln!("redirection error: {}", msg); return; }
-//             let mut pipe_fds: [i32; 2] = [0; 2];
-//             let use_pipe: bool = part.direction == Some(Direction::Pipe);
-
-//             // Create a pipe only if this command is piping to the next
-//             if use_pipe {
-//                 unsafe {
-//                     if pipe(pipe_fds.as_mut_ptr()) == -1 {
-//                         panic!("pipe failed!");
-//                     }
-//                 }
-//             }
-
-//             unsafe {
-//                 let pid = fork();
-//                 if pid < 0 {
-//                     panic!("fork failed!");
-//                 } else if pid == 0 {
-//                     //CHILD
-//                     if let Some(filename) = &part.redir_out {
-//                         let file = CString::new(filename.clone()).unwrap();
-//                         let fd = open(file.as_ptr(), O_WRONLY | O_CREAT | O_TRUNC, 0o600);
-//                         if fd == -1 {
-//                             eprintln!("open failed for output '{}'", filename);
-//                             std::process::exit(1);
-//                         }
-//                         dup2(fd, STDOUT_FILENO);
-//                         close(fd);
-//                     }
-//                     if let Some(filename) = &part.redir_in {
-//                         let file = CString::new(filename.clone()).unwrap();
-//                         let fd = open(file.as_ptr(), O_RDONLY);
-//                         if fd == -1 {
-//                             eprintln!("input file not found '{}'", filename);
-//                             std::process::exit(1);
-//                         }
-//                         if !is_regular_fd(fd) {
-//                             eprintln!("input is not a regular file '{}'", filename);
-//                             close(fd);
-//                             std::process::exit(1);
-//                         }
-//                         dup2(fd, STDIN_FILENO);
-//                         close(fd);
-//                     }
-                
-//                     // If there was a previous pipe, set stdin to its read end
-//                     if let Some(fd) = previous_fd {
-//                         dup2(fd, STDIN_FILENO);
-//                     }
-
-//                     // If we're piping to the next, set stdout to this pipe's write end
-//                     if use_pipe {
-//                         dup2(pipe_fds[1], STDOUT_FILENO);
-//                     }
-
-//                     // Close unused fds in child
-//                     if let Some(fd) = previous_fd {
-//                         close(fd);
-//                     }
-//                     if use_pipe {
-//                         close(pipe_fds[0]);
-//                         close(pipe_fds[1]);
-//                     }
-
-//                     // Prepare argv
-//                     let mut argv = vec![part.program.as_ptr()];
-//                     for arg in &part.args {
-//                         argv.push(arg.as_ptr());
-//                     }
-//                     argv.push(ptr::null());
-
-//                     execv(part.program.as_ptr(), argv.as_ptr());
-
-//                     //friendly exec failure messages
-//                     let err = std::io::Error::last_os_error();
-//                     if let Some(code) = err.raw_os_error() {
-//                         if code == ENOENT {
-//                             eprintln!("command not found");
-//                         } else {
-//                             eprintln!("exec failed: {}", err);
-//                         }
-//                     } else {
-//                         eprintln!("exec failed");
-//                     }
-//                     std::process::exit(127);
-//                 } else {
-//                     // PARENT
-//                     if let Some(fd) = previous_fd {
-//                         libc::close(fd); // close previous pipe's read end
-//                     }
-//                     if use_pipe {
-//                         libc::close(pipe_fds[1]); // close write end in parent
-//                         previous_fd = Some(pipe_fds[0]); // keep read end for next iteration
-//                     } else {
-//                         previous_fd = None;
-//                     }
-//                     if !part.background {
-//                         waitpid(pid, ptr::null_mut(), 0);
-//                     }
-//                 }
-//             }
-//         }
-//     }
-// }
-
-
-//Public entry used by main.rs to execute a line and register background jobs
-pub fn execute_command_with_jobs(command: &str, jobs: &mut JobTable) {
-    // Phase 1: Tokenization and Expansion
-    let tokens: Vec<_> = tokenize(command);
-    let expanded_tokens: Vec<CString> = expand_tokens(tokens);
-
-    // Phase 2: Interpretation and Execution
-    let commands: Vec<CommandPart> = interpret_tokens(expanded_tokens);
-    execute_with_jobs(commands, jobs, command);
-}
-
-
-// Variant of execute() that also records background jobs
-fn execute_with_jobs(command_parts: Vec<CommandPart>, jobs: &mut JobTable, cmdline: &str) {
-    // Single command
-    if command_parts.len() == 1 {
-        unsafe {
-            let cmd = &command_parts[0];
-            if let Some(msg) = cmd.parse_error { eprintln!("redirection error: {}", msg); return; }
-            let pid: i32 = fork();
-            if pid < 0 {
-                eprintln!("Fork failed!");
-            } else if pid == 0 {
-                // CHILD: reuse your redirection + exec path
-                if let Some(filename) = &cmd.redir_out {
-                    let file = CString::new(filename.clone()).unwrap();
-                    let fd = open(file.as_ptr(), O_WRONLY | O_CREAT | O_TRUNC, 0o600);
-                    if fd == -1 { eprintln!("open failed for output '{}'", filename); std::process::exit(1); }
-                    dup2(fd, STDOUT_FILENO); close(fd);
-                }
-                if let Some(filename) = &cmd.redir_in {
-                    let file = CString::new(filename.clone()).unwrap();
-                    let fd = open(file.as_ptr(), O_RDONLY);
-                    if fd == -1 { eprintln!("input file not found '{}'", filename); std::process::exit(1); }
-                    if !is_regular_fd(fd) { eprintln!("input is not a regular file '{}'", filename); close(fd); std::process::exit(1); }
-                    dup2(fd, STDIN_FILENO); close(fd);
-                }
-                let mut argv = vec![cmd.program.as_ptr()];
-                for arg in cmd.args.iter() { argv.push(arg.as_ptr()); }
-                argv.push(ptr::null());
-                execv(cmd.program.as_ptr(), argv.as_ptr());
-                let err = std::io::Error::last_os_error();
-                if let Some(code) = err.raw_os_error() { if code == ENOENT { eprintln!("command not found"); } else { eprintln!("exec failed: {}", err); } }
-                else { eprintln!("exec failed"); }
-                std::process::exit(127);
-            } else {
-                if cmd.background {
-                    // Register the background job (single command: child's PID)
-                    let _ = jobs.add_job(pid, cmdline.to_string());
-                } else {
-                    waitpid(pid, ptr::null_mut(), 0);
-                }
+        (st.st_mode & S_IFMT) == S_IFREG
+    }
+}
+
+//Public entry used by main.rs to run a parsed command line and register background jobs.
+//Returns the exit status of the last pipeline that ran.
+pub fn execute_list(list: &List, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+    for item in &list.items {
+        status = execute_item(item, shell);
+        // later entries in the same list can see this one's status in $?
+        shell.last_status = status;
+        // `break` / `continue` skips the rest of the loop body
+        if shell.control.is_some() {
+            break;
+        }
+    }
+    status
+}
+
+/// Run `command` in a forked copy of the shell and return what it wrote to
+/// stdout, minus trailing newlines. Its exit status becomes $?.
+pub fn command_substitution(command: &str, shell: &mut Shell) -> String {
+    let mut pipe_fds: [i32; 2] = [0; 2];
+    unsafe { if pipe(pipe_fds.as_mut_ptr()) == -1 { panic!("pipe failed!"); } }
+
+    let _ = stdout().flush();
+    let pid = unsafe { fork() };
+    if pid < 0 {
+        eprintln!("Fork failed!");
+        unsafe { close(pipe_fds[0]); close(pipe_fds[1]); }
+        return String::new();
+    } else if pid == 0 {
+        unsafe { dup2(pipe_fds[1], STDOUT_FILENO); close(pipe_fds[0]); close(pipe_fds[1]); }
+        // the parent's background jobs aren't this process's children
+        shell.jobs = JobTable::new();
+        let status = match parse(command) {
+            Ok(list) => execute_list(&list, shell),
+            Err(e) => {
+                eprintln!("syntax error: {}", e);
+                2
             }
+        };
+        std::process::exit(status);
+    }
+
+    unsafe { close(pipe_fds[1]); }
+    let mut output = Vec::new();
+    let mut reader = unsafe { File::from_raw_fd(pipe_fds[0]) };
+    let _ = reader.read_to_end(&mut output);
+    drop(reader);
+    shell.last_status = wait_for(pid);
+
+    // a NUL can't be part of a word, so it is dropped, as bash does
+    if output.contains(&0) {
+        eprintln!("warning: command substitution: ignored null byte in input");
+        output.retain(|&byte| byte != 0);
+    }
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    while output.ends_with('\n') {
+        output.pop();
+    }
+    output
+}
+
+fn execute_item(item: &ListItem, shell: &mut Shell) -> i32 {
+    if !item.background {
+        return execute_and_or(&item.and_or, shell);
+    }
+    // A lone pipeline registers its last stage as the job
+    if item.and_or.rest.is_empty() {
+        return execute_pipeline(&item.and_or.first, shell, Some(&item.text));
+    }
+    // A whole && / || chain is run by a forked copy of the shell
+    let _ = stdout().flush();
+    let pid = unsafe { fork() };
+    if pid < 0 {
+        eprintln!("Fork failed!");
+        return 1;
+    } else if pid == 0 {
+        let status = execute_and_or(&item.and_or, shell);
+        std::process::exit(status);
+    }
+    let _ = shell.jobs.add_job(pid, item.text.clone());
+    0
+}
+
+fn execute_and_or(and_or: &AndOr, shell: &mut Shell) -> i32 {
+    let mut status = execute_pipeline(&and_or.first, shell, None);
+    for (connector, pipeline) in &and_or.rest {
+        if shell.control.is_some() {
+            break;
+        }
+        shell.last_status = status;
+        // A skipped pipeline leaves the status alone, so `false && a || b` runs b
+        let run = match connector {
+            Connector::And => status == 0,
+            Connector::Or => status != 0,
+        };
+        if run {
+            status = execute_pipeline(pipeline, shell, None);
         }
-        return;
+    }
+    status
+}
+
+// Run a pipeline. `background` carries the job's command line when it was started with `&`.
+fn execute_pipeline(pipeline: &Pipeline, shell: &mut Shell, background: Option<&str>) -> i32 {
+    // A single foreground command runs in the shell itself so builtins like cd take effect
+    if pipeline.commands.len() == 1 && background.is_none() {
+        return execute_command(&pipeline.commands[0], shell, false);
     }
 
-    // Pipeline (up to two pipes per spec)
     let mut previous_fd: Option<i32> = None;
-    let mut last_pid: i32 = -1;
-    let mut last_part_background: bool = false;
+    let mut pids: Vec<i32> = Vec::new();
+    let last = pipeline.commands.len() - 1;
 
-    for part in command_parts.iter() {
-        if let Some(msg) = part.parse_error { eprintln!("redirection error: {}", msg); return;}
+    for (i, cmd) in pipeline.commands.iter().enumerate() {
         let mut pipe_fds: [i32; 2] = [0; 2];
-        let use_pipe: bool = part.direction == Some(Direction::Pipe);
-        last_part_background = part.background; // only set on final part if '&' present
+        let use_pipe = i < last;
 
         if use_pipe {
             unsafe { if pipe(pipe_fds.as_mut_ptr()) == -1 { panic!("pipe failed!"); } }
         }
 
+        let _ = stdout().flush();
         unsafe {
             let pid = fork();
             if pid < 0 {
                 panic!("fork failed!");
             } else if pid == 0 {
-                // CHILD: redirections
-                if let Some(filename) = &part.redir_out {
-                    let file = CString::new(filename.clone()).unwrap();
-                    let fd = open(file.as_ptr(), O_WRONLY | O_CREAT | O_TRUNC, 0o600);
-                    if fd == -1 { eprintln!("open failed for output '{}'", filename); std::process::exit(1); }
-                    dup2(fd, STDOUT_FILENO); close(fd);
-                }
-                if let Some(filename) = &part.redir_in {
-                    let file = CString::new(filename.clone()).unwrap();
-                    let fd = open(file.as_ptr(), O_RDONLY);
-                    if fd == -1 { eprintln!("input file not found '{}'", filename); std::process::exit(1); }
-                    if !is_regular_fd(fd) { eprintln!("input is not a regular file '{}'", filename); close(fd); std::process::exit(1); }
-                    dup2(fd, STDIN_FILENO); close(fd);
-                }
-                if let Some(fd) = previous_fd { dup2(fd, STDIN_FILENO); }
-                if use_pipe { dup2(pipe_fds[1], STDOUT_FILENO); }
-                if let Some(fd) = previous_fd { close(fd); }
-                if use_pipe { close(pipe_fds[0]); close(pipe_fds[1]); }
-
-                // exec
-                let mut argv = vec![part.program.as_ptr()];
-                for arg in &part.args { argv.push(arg.as_ptr()); }
-                argv.push(ptr::null());
-                execv(part.program.as_ptr(), argv.as_ptr());
-                let err = std::io::Error::last_os_error();
-                if let Some(code) = err.raw_os_error() { if code == ENOENT { eprintln!("command not found"); } else { eprintln!("exec failed: {}", err); } }
-                else { eprintln!("exec failed"); }
-                std::process::exit(127);
+                // CHILD: connect to the neighbouring stages, then run the command
+                if let Some(fd) = previous_fd { dup2(fd, STDIN_FILENO); close(fd); }
+                if use_pipe { dup2(pipe_fds[1], STDOUT_FILENO); close(pipe_fds[0]); close(pipe_fds[1]); }
+                let status = execute_command(cmd, shell, true);
+                std::process::exit(status);
             } else {
                 // PARENT
-                if let Some(fd) = previous_fd { libc::close(fd); }
+                if let Some(fd) = previous_fd { close(fd); }
                 if use_pipe {
-                    libc::close(pipe_fds[1]);
+                    close(pipe_fds[1]);
                     previous_fd = Some(pipe_fds[0]);
                 } else {
                     previous_fd = None;
                 }
-                last_pid = pid;
+                pids.push(pid);
             }
         }
     }
 
+    let last_pid = pids[last];
+    if let Some(cmdline) = background {
+        // Background pipeline: register last stage’s PID
+        let _ = shell.jobs.add_job(last_pid, cmdline.to_string());
+        return 0;
+    }
+    // Foreground pipeline: reap every stage, the last stage's status is the pipeline's status
+    let mut status = 0;
+    for pid in pids {
+        status = wait_for(pid);
+    }
+    status
+}
+
+// Run a command in the shell process, forking only to start external
+// programs. In an already-forked child (`in_child`) they replace it instead.
+fn execute_command(cmd: &Command, shell: &mut Shell, in_child: bool) -> i32 {
+    match cmd {
+        Command::Simple(simple) => execute_simple(simple, shell, in_child),
+        Command::Arith(expr) => execute_arith(expr, shell),
+        Command::Compound(compound, redirects) => execute_compound(compound, redirects, shell),
+        Command::Function(function) => define_function(function, shell),
+    }
+}
+
+fn define_function(function: &FunctionDef, shell: &mut Shell) -> i32 {
+    shell.functions.insert(function.name.clone(), Rc::new(function.clone()));
+    0
+}
+
+// Run a function with the arguments as its positional parameters and a new
+// frame for `local`. Loops the caller is in can't be left from inside it.
+fn call_function(function: &FunctionDef, args: &[String], shell: &mut Shell) -> i32 {
+    let limit = shell.get_var("FUNCNEST").and_then(|n| n.parse().ok()).filter(|&n| n > 0).unwrap_or(MAX_FUNCTION_DEPTH);
+    if shell.locals.len() >= limit {
+        eprintln!("{}: maximum function nesting level exceeded ({})", function.name, limit);
+        shell.control = Some(Control::Abort);
+        return 1;
+    }
+    let positional = std::mem::replace(&mut shell.positional, args.to_vec());
+    let loop_depth = std::mem::replace(&mut shell.loop_depth, 0);
+    shell.locals.push(Vec::new());
+
+    let status = execute_command(&function.body, shell, false);
+    if shell.control == Some(Control::Return) {
+        shell.control = None;
+    }
+
+    if let Some(frame) = shell.locals.pop() {
+        restore_vars(frame, shell);
+    }
+    shell.loop_depth = loop_depth;
+    shell.positional = positional;
+    status
+}
+
+// Run a compound command in the current process with its redirections
+// applied around the whole thing
+fn execute_compound(compound: &CompoundCommand, redirects: &[Redirect], shell: &mut Shell) -> i32 {
+    let saved = match apply_redirects(redirects, shell, true) {
+        Ok(saved) => saved,
+        Err(()) => return 1,
+    };
+    let status = match compound {
+        CompoundCommand::Group(list) => execute_list(list, shell),
+        CompoundCommand::Subshell(list) => execute_subshell(list, shell),
+        CompoundCommand::If(command) => execute_if(command, shell),
+        CompoundCommand::While(command) => execute_while(command, shell),
+        CompoundCommand::For(command) => execute_for(command, shell),
+        CompoundCommand::ArithFor(command) => execute_arith_for(command, shell),
+        CompoundCommand::Case(command) => execute_case(command, shell),
+    };
+    restore_fds(saved);
+    status
+}
+
+// Run a list in a forked copy of the shell, so `cd`, assignments and the
+// like don't reach this one
+fn execute_subshell(list: &List, shell: &mut Shell) -> i32 {
+    let _ = stdout().flush();
+    let pid = unsafe { fork() };
+    if pid < 0 {
+        eprintln!("Fork failed!");
+        return 1;
+    } else if pid == 0 {
+        // the parent's background jobs aren't this process's children
+        shell.jobs = JobTable::new();
+        let status = execute_list(list, shell);
+        std::process::exit(status);
+    }
+    wait_for(pid)
+}
+
+// Run the list of the first item with a pattern matching the word; `;&`
+// runs the next list as well and `;;&` keeps testing patterns. The status is
+// the last list's, or 0 if nothing matched.
+fn execute_case(command: &CaseCommand, shell: &mut Shell) -> i32 {
+    let word = match expand_word(&command.word, shell) {
+        Ok(word) => word,
+        Err(ExpandError) => return 1,
+    };
+    let mut status = 0;
+    let mut fall_through = false;
+    for item in &command.items {
+        if !fall_through {
+            let mut matched = false;
+            for pattern in &item.patterns {
+                match expand_pattern(pattern, shell) {
+                    Ok(pattern) if pattern_matches(&pattern, &word) => {
+                        matched = true;
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(ExpandError) => return 1,
+                }
+            }
+            if !matched {
+                continue;
+            }
+        }
+        status = execute_list(&item.body, shell);
+        if shell.control.is_some() {
+            break;
+        }
+        match item.terminator {
+            CaseTerminator::Break => break,
+            CaseTerminator::FallThrough => fall_through = true,
+            CaseTerminator::Continue => fall_through = false,
+        }
+    }
+    status
+}
+
+// Deal with any `break` / `continue` run by a loop's body or condition.
+// Returns false when the loop should stop. A count above one leaves the
+// rest pending for the enclosing loops; a `return` is left for the function
+// and an abort for the top level.
+fn loop_continues(shell: &mut Shell) -> bool {
+    match shell.control {
+        None => true,
+        Some(Control::Return | Control::Abort) => false,
+        Some(Control::Break(n)) => {
+            shell.control = (n > 1).then_some(Control::Break(n - 1));
+            false
+        }
+        Some(Control::Continue(n)) => {
+            shell.control = (n > 1).then_some(Control::Continue(n - 1));
+            n == 1
+        }
+    }
+}
+
+// Run a loop with shell.loop_depth raised so `break` and `continue` know
+// they are inside it
+fn in_loop(shell: &mut Shell, run: impl FnOnce(&mut Shell) -> i32) -> i32 {
+    shell.loop_depth += 1;
+    let status = run(shell);
+    shell.loop_depth -= 1;
+    status
+}
+
+// The status is the last body run's, or 0 if the body never ran
+fn execute_while(command: &WhileCommand, shell: &mut Shell) -> i32 {
+    in_loop(shell, |shell| {
+        let mut status = 0;
+        loop {
+            let condition = execute_list(&command.condition, shell);
+            if shell.control.is_some() {
+                if !loop_continues(shell) {
+                    break;
+                }
+                continue;
+            }
+            if (condition == 0) == command.until {
+                break;
+            }
+            status = execute_list(&command.body, shell);
+            if !loop_continues(shell) {
+                break;
+            }
+        }
+        status
+    })
+}
+
+fn execute_for(command: &ForCommand, shell: &mut Shell) -> i32 {
+    let values = match &command.words {
+        Some(words) => match expand_words(words, shell) {
+            Ok(values) => values,
+            Err(ExpandError) => return 1,
+        },
+        None => shell.positional.clone(),
+    };
+    in_loop(shell, |shell| {
+        let mut status = 0;
+        for value in values {
+            if let Err(message) = shell.set_var(&command.name, &value) {
+                eprintln!("{}", message);
+                return 1;
+            }
+            status = execute_list(&command.body, shell);
+            if !loop_continues(shell) {
+                break;
+            }
+        }
+        status
+    })
+}
+
+fn execute_arith_for(command: &ArithForCommand, shell: &mut Shell) -> i32 {
+    // an empty part is skipped; an empty condition is always true
+    let arith = |word: &Word, shell: &mut Shell| if word.is_empty() { Ok(1) } else { expand_arith(word, shell) };
+    in_loop(shell, |shell| {
+        let mut status = 0;
+        if arith(&command.init, shell).is_err() {
+            return 1;
+        }
+        loop {
+            match arith(&command.condition, shell) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(ExpandError) => return 1,
+            }
+            status = execute_list(&command.body, shell);
+            if !loop_continues(shell) {
+                break;
+            }
+            if arith(&command.step, shell).is_err() {
+                return 1;
+            }
+        }
+        status
+    })
+}
+
+// The body of the first branch whose condition succeeds; 0 if none runs
+fn execute_if(command: &IfCommand, shell: &mut Shell) -> i32 {
+    for (condition, body) in &command.branches {
+        let status = execute_list(condition, shell);
+        // `break`, `continue` or `return` in the condition leaves the if as well
+        if shell.control.is_some() {
+            return status;
+        }
+        if status == 0 {
+            return execute_list(body, shell);
+        }
+    }
+    match &command.otherwise {
+        Some(otherwise) => execute_list(otherwise, shell),
+        None => 0,
+    }
+}
+
+// (( expr )) succeeds when the expression is non-zero
+fn execute_arith(expr: &Word, shell: &mut Shell) -> i32 {
+    match expand_arith(expr, shell) {
+        Ok(value) => (value == 0) as i32,
+        Err(ExpandError) => 1,
+    }
+}
+
+fn execute_simple(cmd: &SimpleCommand, shell: &mut Shell, in_child: bool) -> i32 {
+    let (words, arrays) = match expand_args(&cmd.words, shell) {
+        Ok(args) => args,
+        Err(ExpandError) => return 1,
+    };
+
+    // Assignments on their own set shell variables for good
+    if words.is_empty() {
+        for assignment in &cmd.assignments {
+            if assign(assignment, shell).is_err() {
+                return 1;
+            }
+        }
+        let saved = match apply_redirects(&cmd.redirects, shell, true) {
+            Ok(saved) => saved,
+            Err(()) => return 1,
+        };
+        restore_fds(saved);
+        // `X=$(cmd)` has the status of the substitution
+        let substituted = cmd.assignments.iter().any(|a| a.value.iter().any(|p| matches!(p, WordPart::CommandSub { .. })));
+        return if substituted { shell.last_status } else { 0 };
+    }
+
+    // Assignments before a command are exported to it and undone afterwards
+    let mut previous = Vec::new();
+    for assignment in &cmd.assignments {
+        let old = shell.vars.get(&assignment.name).cloned();
+        if assign(assignment, shell).is_err() {
+            restore_vars(previous, shell);
+            return 1;
+        }
+        shell.vars.get_or_create(&assignment.name).attrs.exported = true;
+        previous.push((assignment.name.clone(), old));
+    }
+    let status = run_command(&words, &arrays, cmd, shell, in_child);
+    restore_vars(previous, shell);
+    status
+}
+
+// Expand a command's words. For a declaration builtin each `name=(...)`
+// argument comes out as `name=`, with its expanded items at the same index
+// of the second list; otherwise that list is empty.
+fn expand_args(words: &[Word], shell: &mut Shell) -> Result<(Vec<String>, Vec<ArrayArg>), ExpandError> {
+    let declaration = matches!(words.first().map(Vec::as_slice),
+        Some([WordPart::Literal(name)]) if DECLARATION_BUILTINS.contains(&name.as_str()) && !shell.functions.contains_key(name));
+    if !declaration {
+        return Ok((expand_words(words, shell)?, Vec::new()));
+    }
+    let mut args = Vec::new();
+    let mut arrays = Vec::new();
+    for word in words {
+        if let Some(Assignment { name, index: None, append: false, value }) = split_assignment(word)
+            && let [WordPart::Array(items)] = value.as_slice()
+        {
+            args.push(format!("{}=", name));
+            arrays.push(Some(expand_array_items(items, shell)?));
+            continue;
+        }
+        for arg in expand_words(std::slice::from_ref(word), shell)? {
+            args.push(arg);
+            arrays.push(None);
+        }
+    }
+    Ok((args, arrays))
+}
+
+// Carry out one assignment: `NAME=value`, `NAME[index]=value` or
+// `NAME=(items)`, with `+=` appending to the current value (adding, for an
+// integer variable). Errors are printed here.
+fn assign(assignment: &Assignment, shell: &mut Shell) -> Result<(), ExpandError> {
+    let name = &assignment.name;
+    let result = match (&assignment.index, assignment.value.as_slice()) {
+        (None, [WordPart::Array(items)]) => {
+            let items = expand_array_items(items, shell)?;
+            shell.assign_array(name, items, assignment.append)
+        }
+        (None, _) => {
+            let mut value = expand_word(&assignment.value, shell)?;
+            if assignment.append {
+                value = appended(shell.get_var(name), value, name, shell);
+            }
+            shell.set_var(name, &value)
+        }
+        (Some(index), _) => {
+            let subscript = expand_word(index, shell)?;
+            let mut value = expand_word(&assignment.value, shell)?;
+            if assignment.append {
+                let current = shell.get_element(name, &subscript).unwrap_or_default();
+                value = appended(current, value, name, shell);
+            }
+            shell.set_element(name, &subscript, &value)
+        }
+    };
+    result.map_err(|message| {
+        eprintln!("{}", message);
+        ExpandError
+    })
+}
+
+// The value for `+=`: the expression `current + value` for an integer
+// variable, which the assignment then evaluates, otherwise the two joined
+fn appended(current: Option<String>, value: String, name: &str, shell: &Shell) -> String {
+    let current = current.unwrap_or_default();
+    if shell.vars.get(name).is_some_and(|var| var.attrs.integer) {
+        format!("{}+({})", if current.is_empty() { "0" } else { &current }, value)
+    } else {
+        current + &value
+    }
+}
+
+// Put back variables replaced by a command's prefix assignments, newest first
+// so a name assigned twice ends up with its original value
+fn restore_vars(previous: Vec<(String, Option<Var>)>, shell: &mut Shell) {
+    for (name, var) in previous.into_iter().rev() {
+        shell.vars.replace(&name, var);
+    }
+}
+
+fn run_command(words: &[String], arrays: &[ArrayArg], cmd: &SimpleCommand, shell: &mut Shell, in_child: bool) -> i32 {
+    // Functions run in-process with the redirections applied around them,
+    // ahead of builtins so a function can wrap `cd` or `read`
+    if let Some(function) = shell.functions.get(&words[0]).cloned() {
+        let saved = match apply_redirects(&cmd.redirects, shell, true) {
+            Ok(saved) => saved,
+            Err(()) => return 1,
+        };
+        let status = call_function(&function, &words[1..], shell);
+        restore_fds(saved);
+        return status;
+    }
+
+    // Builtins run in-process the same way, ahead of the PATH search
+    if is_builtin(&words[0]) {
+        let saved = match apply_redirects(&cmd.redirects, shell, true) {
+            Ok(saved) => saved,
+            Err(()) => return 1,
+        };
+        let status = match try_builtin(&words.join(" "), words, arrays, shell) {
+            BuiltinResult::Handled(status) => status,
+            BuiltinResult::NotHandled => 0,
+        };
+        restore_fds(saved);
+        return status;
+    }
+
+    let args: Result<Vec<CString>, _> = words[1..].iter().map(|w| CString::new(w.as_str())).collect();
+    let (Some(program), Ok(args)) = (resolve_path(&words[0], shell), args) else {
+        eprintln!("{}: arguments can't contain a null byte", words[0].replace('\0', ""));
+        return 1;
+    };
+    let env = shell.vars.environment();
+
+    if in_child {
+        if apply_redirects(&cmd.redirects, shell, false).is_err() { std::process::exit(1); }
+        exec_program(&program, &args, &env);
+    }
+
+    let _ = stdout().flush();
+    let pid: i32 = unsafe { fork() };
+    if pid < 0 {
+        eprintln!("Fork failed!");
+        1
+    } else if pid == 0 {
+        if apply_redirects(&cmd.redirects, shell, false).is_err() { std::process::exit(1); }
+        exec_program(&program, &args, &env);
+    } else {
+        wait_for(pid)
+    }
+}
+
+fn exec_program(program: &CString, args: &[CString], env: &[CString]) -> ! {
+    let mut argv = vec![program.as_ptr()];
+    for arg in args { argv.push(arg.as_ptr()); }
+    argv.push(ptr::null());
+    let mut envp: Vec<*const c_char> = env.iter().map(|var| var.as_ptr()).collect();
+    envp.push(ptr::null());
+    unsafe { execve(program.as_ptr(), argv.as_ptr(), envp.as_ptr()); }
+    // a file the kernel won't run (no `#!` line) is taken to be a script for
+    // this shell, as other shells do
+    if std::io::Error::last_os_error().raw_os_error() == Some(ENOEXEC)
+        && let Ok(exe) = std::env::current_exe()
+        && let Ok(exe) = CString::new(exe.into_os_string().into_vec())
+    {
+        argv.insert(0, exe.as_ptr());
+        unsafe { execve(exe.as_ptr(), argv.as_ptr(), envp.as_ptr()); }
+    }
+    exec_failed();
+}
+
+// Apply a command's redirections in source order, so `> f 2>&1` sends both
+// streams to f while `2>&1 > f` leaves stderr on the old stdout. With `save`
+// set, every fd is duplicated before it is first changed and the
+// (fd, saved copy) pairs are returned so restore_fds can undo the redirections;
+// a saved copy of -1 means the fd was closed beforehand.
+fn apply_redirects(redirects: &[Redirect], shell: &mut Shell, save: bool) -> Result<Vec<(i32, i32)>, ()> {
+    let mut saved: Vec<(i32, i32)> = Vec::new();
+    for redirect in redirects {
+        let target = match expand_word(&redirect.target, shell) {
+            Ok(target) => target,
+            Err(ExpandError) => {
+                restore_fds(saved);
+                return Err(());
+            }
+        };
+        let fd = redirect.fd.unwrap_or(redirect.kind.default_fd());
+        // &> (and >& followed by a file name) redirect stdout and stderr together
+        let fds: &[i32] = match redirect.kind {
+            RedirKind::OutputAll | RedirKind::AppendAll => &[1, 2],
+            RedirKind::DupOutput if redirect.fd.is_none() && !is_dup_target(&target) => &[1, 2],
+            _ => std::slice::from_ref(&fd),
+        };
+        if save {
+            let _ = stdout().flush();
+            for &fd in fds {
+                if !saved.iter().any(|&(f, _)| f == fd) {
+                    saved.push((fd, unsafe { fcntl(fd, F_DUPFD_CLOEXEC, 10) }));
+                }
+            }
+        }
+        if apply_redirect(redirect.kind, fds, &target).is_err() {
+            restore_fds(saved);
+            return Err(());
+        }
+    }
+    Ok(saved)
+}
+
+// `-` (close) or an fd number, the things that can follow `>&` / `<&`
+fn is_dup_target(target: &str) -> bool {
+    target == "-" || (!target.is_empty() && target.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn apply_redirect(kind: RedirKind, fds: &[i32], target: &str) -> Result<(), ()> {
     unsafe {
-        if last_part_background {
-            // Background pipeline: register last stage’s PID
-            let _ = jobs.add_job(last_pid, cmdline.to_string());
-        } else {
-            // Foreground pipeline: wait for the last stage (simple semantics)
-            waitpid(last_pid, ptr::null_mut(), 0);
+        if matches!(kind, RedirKind::DupInput | RedirKind::DupOutput) && is_dup_target(target) {
+            if target == "-" {
+                close(fds[0]);
+                return Ok(());
+            }
+            let source: i32 = match target.parse() {
+                Ok(source) => source,
+                Err(_) => { eprintln!("{}: bad file descriptor", target); return Err(()); }
+            };
+            if dup2(source, fds[0]) == -1 {
+                eprintln!("{}: bad file descriptor", target);
+                return Err(());
+            }
+            return Ok(());
+        }
+
+        let file = match CString::new(target) {
+            Ok(file) => file,
+            // a document's text is never used as a path
+            Err(_) if matches!(kind, RedirKind::HereDoc | RedirKind::HereString) => CString::default(),
+            Err(_) => { eprintln!("{}: file name can't contain a null byte", target.replace('\0', "")); return Err(()); }
+        };
+        let fd = match kind {
+            RedirKind::HereDoc | RedirKind::HereString => {
+                // a here-string gets the trailing newline a here-document body already has
+                let text = if kind == RedirKind::HereString { format!("{}\n", target) } else { target.to_string() };
+                let fd = document_fd(&text);
+                if fd == -1 {
+                    eprintln!("cannot create temp file for here-document");
+                    return Err(());
+                }
+                fd
+            }
+            RedirKind::Input | RedirKind::DupInput => {
+                let fd = open(file.as_ptr(), O_RDONLY);
+                if fd == -1 {
+                    eprintln!("input file not found '{}'", target);
+                    return Err(());
+                }
+                if !is_regular_fd(fd) {
+                    eprintln!("input is not a regular file '{}'", target);
+                    close(fd);
+                    return Err(());
+                }
+                fd
+            }
+            RedirKind::ReadWrite => open(file.as_ptr(), O_RDWR | O_CREAT, 0o600),
+            RedirKind::Append | RedirKind::AppendAll => open(file.as_ptr(), O_WRONLY | O_CREAT | O_APPEND, 0o600),
+            RedirKind::Output | RedirKind::OutputAll | RedirKind::DupOutput => {
+                open(file.as_ptr(), O_WRONLY | O_CREAT | O_TRUNC, 0o600)
+            }
+        };
+        if fd == -1 {
+            eprintln!("open failed for output '{}'", target);
+            return Err(());
+        }
+        for &target_fd in fds {
+            dup2(fd, target_fd);
+        }
+        if !fds.contains(&fd) {
+            close(fd);
         }
     }
+    Ok(())
 }
 
+// Store here-document text in an unlinked temp file and return it open for
+// reading from the start, ready to be installed as the command's stdin
+fn document_fd(text: &str) -> i32 {
+    let mut template = *b"/tmp/shell-heredoc-XXXXXX\0";
+    unsafe {
+        let fd = mkstemp(template.as_mut_ptr() as *mut c_char);
+        if fd == -1 {
+            return -1;
+        }
+        unlink(template.as_ptr() as *const c_char);
+        let mut file = File::from_raw_fd(fd);
+        if file.write_all(text.as_bytes()).is_err() || lseek(fd, 0, SEEK_SET) == -1 {
+            return -1; // dropping the file closes it
+        }
+        file.into_raw_fd()
+    }
+}
 
+// Undo apply_redirects, newest first so a fd redirected twice ends up original
+fn restore_fds(saved: Vec<(i32, i32)>) {
+    let _ = stdout().flush();
+    for (target, copy) in saved.into_iter().rev() {
+        unsafe {
+            if copy == -1 {
+                close(target);
+            } else {
+                dup2(copy, target);
+                close(copy);
+            }
+        }
+    }
+}
+
+// Report why execv failed and exit the child with the conventional status:
+// 127 when the command doesn't exist, 126 when it exists but can't be run
+fn exec_failed() -> ! {
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(ENOENT) {
+        eprintln!("command not found");
+        std::process::exit(127);
+    }
+    eprintln!("exec failed: {}", err);
+    std::process::exit(126);
+}
 
+// Block until pid exits and decode its status the way shells report it:
+// the exit code for a normal exit, 128 + signal number for a killed process
+fn wait_for(pid: i32) -> i32 {
+    let mut status: i32 = 0;
+    if unsafe { waitpid(pid, &mut status, 0) } == -1 {
+        return 1;
+    }
+    if WIFEXITED(status) {
+        WEXITSTATUS(status)
+    } else if WIFSIGNALED(status) {
+        128 + WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn function_nesting_limit_aborts() {
+        let mut shell = Shell::new();
+        shell.set_var("FUNCNEST", "20").unwrap();
+        // without the abort this would make 2^20 calls
+        let list = parse("r() { r; r; }; r; reached=1").unwrap();
+        assert_eq!(execute_list(&list, &mut shell), 1);
+        assert_eq!(shell.control, Some(Control::Abort));
+        assert_eq!(shell.get_var("reached"), None);
+        assert!(shell.locals.is_empty());
+    }
+}
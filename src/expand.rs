@@ -0,0 +1,543 @@
+use std::ffi::{CStr, CString};
+use crate::arith::evaluate;
+use crate::builtins::exit_shell;
+use crate::ast::{ArrayItem, ParamExpr, ParamOp, Subscript, Word, WordPart};
+use crate::executor::command_substitution;
+use crate::glob::{escape_pattern, glob, has_wildcards, pattern_matches};
+use crate::shell::{Control, Shell};
+use crate::vars::{self, is_name};
+
+// Field separators when $IFS is unset
+const DEFAULT_IFS: &str = " \t\n";
+
+/// Expansion failed, e.g. `${NAME?}` on an unset variable. The message has
+/// already been printed; the command should not run.
+#[derive(Debug)]
+pub struct ExpandError;
+
+/// Expand the words of a command into its argument list. Runs after parsing,
+/// so nothing a variable expands to can change the command's structure.
+/// Fields containing unquoted pattern characters are matched against the
+/// filesystem; a pattern that matches nothing is kept as typed unless
+/// `nullglob` or `failglob` is set.
+pub fn expand_words(words: &[Word], shell: &mut Shell) -> Result<Vec<String>, ExpandError> {
+    let mut fields = Vec::new();
+    for word in words {
+        for field in expand_fields(word, shell)? {
+            if shell.options.noglob || !has_wildcards(&field.pattern) {
+                fields.push(field.text);
+                continue;
+            }
+            let matches = glob(&field.pattern, shell.options.globstar);
+            if !matches.is_empty() {
+                fields.extend(matches);
+            } else if shell.options.failglob {
+                eprintln!("no match: {}", field.text);
+                return Err(ExpandError);
+            } else if !shell.options.nullglob {
+                fields.push(field.text);
+            }
+        }
+    }
+    Ok(fields)
+}
+
+/// Expand a single word to a single string, e.g. a redirection target.
+pub fn expand_word(word: &Word, shell: &mut Shell) -> Result<String, ExpandError> {
+    word.iter().map(|part| expand_part(part, shell)).collect()
+}
+
+/// Expand the items of `name=(...)`. Keys and keyed values are expanded like
+/// an assignment value; plain values like command words, so `(*.txt)` or
+/// `($list)` can make several elements.
+pub fn expand_array_items(items: &[ArrayItem], shell: &mut Shell) -> Result<Vec<vars::ArrayItem>, ExpandError> {
+    let mut expanded = Vec::new();
+    for item in items {
+        match &item.key {
+            Some(key) => expanded.push((Some(expand_word(key, shell)?), expand_word(&item.value, shell)?)),
+            None => {
+                let values = expand_words(std::slice::from_ref(&item.value), shell)?;
+                expanded.extend(values.into_iter().map(|value| (None, value)));
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+// One expanded field. `pattern` is the same text with everything that was
+// quoted escaped, so only unquoted `*`, `?` and `[` act as wildcards.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+}
+
+impl Field {
+    fn push(&mut self, text: &str, quoted: bool) {
+        self.text.push_str(text);
+        self.pattern.push_str(&if quoted { escape_pattern(text) } else { text.to_string() });
+    }
+}
+
+// Splits a word into fields as its parts are expanded. Results of unquoted
+// parameter expansions and command substitutions are split on $IFS; all
+// other text just extends the current field.
+struct FieldSplitter {
+    ifs: String,
+    fields: Vec<Field>,
+    current: Field,
+    // the current field must be kept even if empty, e.g. it contains ""
+    in_field: bool,
+    // the last field was ended by IFS whitespace, which a following
+    // non-whitespace separator belongs to
+    after_space: bool,
+}
+
+impl FieldSplitter {
+    fn push_text(&mut self, text: &str, quoted: bool) {
+        self.current.push(text, quoted);
+        self.in_field = true;
+        self.after_space = false;
+    }
+
+    // IFS whitespace separates fields but never makes empty ones; any other
+    // IFS character ends exactly one field, which may be empty (`a::b`)
+    fn push_split(&mut self, text: &str) {
+        for c in text.chars() {
+            if !self.ifs.contains(c) {
+                self.push_text(&c.to_string(), false);
+            } else if c.is_whitespace() {
+                if self.in_field {
+                    self.end_field();
+                    self.after_space = true;
+                }
+            } else {
+                if !self.after_space {
+                    self.end_field();
+                }
+                self.after_space = false;
+            }
+        }
+    }
+
+    fn end_field(&mut self) {
+        self.fields.push(std::mem::take(&mut self.current));
+        self.in_field = false;
+    }
+
+    fn finish(mut self) -> Vec<Field> {
+        if self.in_field {
+            self.end_field();
+        }
+        self.fields
+    }
+}
+
+// Expand one word into fields. Unquoted expansions may produce zero or
+// several fields; a word made only of unquoted expansions that come out
+// empty disappears, while quoted text (even "") always makes a field. A
+// quoted "$@" with no items also makes the word disappear unless something
+// else in it has text.
+fn expand_fields(word: &Word, shell: &mut Shell) -> Result<Vec<Field>, ExpandError> {
+    let mut splitter = FieldSplitter {
+        ifs: shell.get_var("IFS").unwrap_or_else(|| String::from(DEFAULT_IFS)),
+        fields: Vec::new(),
+        current: Field::default(),
+        in_field: false,
+        after_space: false,
+    };
+    let mut empty_list = false;
+    let mut has_text = false;
+    for part in word {
+        // "$@", "${arr[@]}" and their unquoted and `*` forms give each item
+        // its own field
+        if let WordPart::Param { expr, quoted } = part
+            && let Some((items, separate)) = param_list(expr, shell)
+            && (separate || !quoted)
+        {
+            empty_list |= *quoted && items.is_empty();
+            has_text |= !items.is_empty();
+            for (i, arg) in items.iter().enumerate() {
+                if i > 0 && (*quoted || splitter.in_field) {
+                    splitter.end_field();
+                }
+                if *quoted { splitter.push_text(arg, true) } else { splitter.push_split(arg) }
+            }
+            continue;
+        }
+        let text = expand_part(part, shell)?;
+        has_text |= !text.is_empty();
+        match part {
+            WordPart::Param { quoted: false, .. } | WordPart::CommandSub { quoted: false, .. } => {
+                splitter.push_split(&text)
+            }
+            _ => splitter.push_text(&text, is_quoted(part)),
+        }
+    }
+    if empty_list && !has_text {
+        return Ok(Vec::new());
+    }
+    Ok(splitter.finish())
+}
+
+// Whether a part's expansion is protected from pattern matching. A tilde's
+// home directory is used as-is.
+fn is_quoted(part: &WordPart) -> bool {
+    match part {
+        WordPart::Quoted(_) | WordPart::Tilde(_) | WordPart::Arith(_) | WordPart::Array(_) => true,
+        WordPart::Param { quoted, .. } | WordPart::CommandSub { quoted, .. } => *quoted,
+        WordPart::Literal(_) => false,
+    }
+}
+
+fn expand_part(part: &WordPart, shell: &mut Shell) -> Result<String, ExpandError> {
+    Ok(match part {
+        WordPart::Literal(s) | WordPart::Quoted(s) => s.clone(),
+        WordPart::Param { expr, .. } => expand_param(expr, shell)?,
+        WordPart::Tilde(user) => expand_tilde(user, shell).unwrap_or_else(|| format!("~{}", user)),
+        WordPart::CommandSub { command, .. } => command_substitution(command, shell),
+        WordPart::Arith(expr) => expand_arith(expr, shell)?.to_string(),
+        // only reaches here outside an assignment, e.g. `echo a=(x y)`, and
+        // stands for the items as written
+        WordPart::Array(items) => {
+            let items: Vec<String> = expand_array_items(items, shell)?
+                .into_iter()
+                .map(|(key, value)| match key {
+                    Some(key) => format!("[{}]={}", key, value),
+                    None => value,
+                })
+                .collect();
+            format!("({})", items.join(" "))
+        }
+    })
+}
+
+// The items a `$@`-like expansion stands for: the positional parameters for
+// $@ and $*, the elements of ${arr[@]} and ${arr[*]}, the keys of
+// ${!arr[@]}. The flag is set for the `@` forms, whose items stay separate
+// fields inside double quotes.
+fn param_list(expr: &ParamExpr, shell: &Shell) -> Option<(Vec<String>, bool)> {
+    let separate = match (&expr.index, expr.name.as_str()) {
+        (Some(Subscript::All), _) | (None, "@") => true,
+        (Some(Subscript::Joined), _) | (None, "*") => false,
+        _ => return None,
+    };
+    let items = match (&expr.op, &expr.index) {
+        (ParamOp::Plain, None) => shell.positional.clone(),
+        (ParamOp::Plain, Some(_)) => shell.array_values(&expr.name),
+        (ParamOp::Keys, Some(_)) => shell.array_keys(&expr.name),
+        _ => return None,
+    };
+    Some((items, separate))
+}
+
+// The directory a tilde-prefix stands for: `~` is $HOME, `~+` and `~-` are
+// $PWD and $OLDPWD, `~user` is that user's home directory. None when there is
+// no such user or variable, in which case the prefix stays as written.
+fn expand_tilde(user: &str, shell: &Shell) -> Option<String> {
+    match user {
+        "" => Some(shell.get_var("HOME").unwrap_or_else(|| String::from("/"))),
+        "+" => shell.get_var("PWD"),
+        "-" => shell.get_var("OLDPWD"),
+        _ => {
+            let name = CString::new(user).ok()?;
+            let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+            if entry.is_null() {
+                return None;
+            }
+            let dir = unsafe { CStr::from_ptr((*entry).pw_dir) };
+            Some(dir.to_string_lossy().into_owned())
+        }
+    }
+}
+
+/// Expand an arithmetic expression's text and evaluate it.
+pub fn expand_arith(expr: &Word, shell: &mut Shell) -> Result<i64, ExpandError> {
+    let text = expand_word(expr, shell)?;
+    evaluate(&text, shell).map_err(|message| {
+        eprintln!("{}: {}", text.trim(), message);
+        ExpandError
+    })
+}
+
+/// Expand a word used as a pattern. Quoted pieces only match themselves, so
+/// their pattern characters are escaped.
+pub fn expand_pattern(word: &Word, shell: &mut Shell) -> Result<String, ExpandError> {
+    let mut field = Field::default();
+    for part in word {
+        field.push(&expand_part(part, shell)?, is_quoted(part));
+    }
+    Ok(field.pattern)
+}
+
+fn expand_param(expr: &ParamExpr, shell: &mut Shell) -> Result<String, ExpandError> {
+    let value = match &expr.index {
+        None => lookup_param(&expr.name, shell),
+        Some(Subscript::Key(word)) => {
+            let subscript = expand_word(word, shell)?;
+            shell.get_element(&expr.name, &subscript).map_err(|message| {
+                eprintln!("{}", message);
+                ExpandError
+            })?
+        }
+        // the whole array joined like $@ / $*; with an operator the operator
+        // applies to the joined value
+        Some(subscript) => {
+            let items = if expr.op == ParamOp::Keys { shell.array_keys(&expr.name) } else { shell.array_values(&expr.name) };
+            let separator = if *subscript == Subscript::All { String::from(" ") } else { ifs_separator(shell) };
+            (!items.is_empty()).then(|| items.join(&separator))
+        }
+    };
+    // the `:` forms treat an empty value like an unset one
+    let is_set = |colon: bool| value.as_ref().is_some_and(|v| !colon || !v.is_empty());
+
+    match &expr.op {
+        ParamOp::Plain | ParamOp::Keys => Ok(value.unwrap_or_default()),
+        ParamOp::Length => match expr.index {
+            Some(Subscript::All | Subscript::Joined) => Ok(shell.array_values(&expr.name).len().to_string()),
+            _ => Ok(value.unwrap_or_default().chars().count().to_string()),
+        },
+        ParamOp::Default { word, colon } => {
+            if is_set(*colon) { Ok(value.unwrap_or_default()) } else { expand_word(word, shell) }
+        }
+        ParamOp::Assign { word, colon } => {
+            if is_set(*colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            if !is_name(&expr.name) {
+                eprintln!("${}: cannot assign in this way", expr.name);
+                return Err(ExpandError);
+            }
+            let new_value = expand_word(word, shell)?;
+            let assigned = match &expr.index {
+                None => shell.set_var(&expr.name, &new_value),
+                Some(Subscript::Key(subscript)) => {
+                    let subscript = expand_word(subscript, shell)?;
+                    shell.set_element(&expr.name, &subscript, &new_value)
+                }
+                Some(_) => Err(format!("{}: bad array subscript", expr.name)),
+            };
+            if let Err(message) = assigned {
+                eprintln!("{}", message);
+                return Err(ExpandError);
+            }
+            // the assigned value after any integer or case attribute
+            Ok(if expr.index.is_none() { shell.get_var(&expr.name).unwrap_or_default() } else { new_value })
+        }
+        ParamOp::Error { word, colon } => {
+            if is_set(*colon) {
+                return Ok(value.unwrap_or_default());
+            }
+            let message = expand_word(word, shell)?;
+            if message.is_empty() {
+                eprintln!("{}: parameter null or not set", expr.name);
+            } else {
+                eprintln!("{}: {}", expr.name, message);
+            }
+            // a script gives up altogether; an interactive shell abandons
+            // the rest of the command line
+            if !shell.interactive {
+                exit_shell(shell, 1);
+            }
+            shell.control = Some(Control::Abort);
+            Err(ExpandError)
+        }
+        ParamOp::Alternative { word, colon } => {
+            if is_set(*colon) { expand_word(word, shell) } else { Ok(String::new()) }
+        }
+        ParamOp::RemovePrefix { pattern, longest } => {
+            let value = value.unwrap_or_default();
+            let pattern = expand_pattern(pattern, shell)?;
+            let chars: Vec<char> = value.chars().collect();
+            let mut ends: Vec<usize> = (0..=chars.len()).collect();
+            if *longest { ends.reverse(); }
+            Ok(ends.into_iter()
+                .find(|&end| pattern_matches(&pattern, &chars[..end].iter().collect::<String>()))
+                .map_or(value.clone(), |end| chars[end..].iter().collect()))
+        }
+        ParamOp::RemoveSuffix { pattern, longest } => {
+            let value = value.unwrap_or_default();
+            let pattern = expand_pattern(pattern, shell)?;
+            let chars: Vec<char> = value.chars().collect();
+            let mut starts: Vec<usize> = (0..=chars.len()).collect();
+            if !*longest { starts.reverse(); }
+            Ok(starts.into_iter()
+                .find(|&start| pattern_matches(&pattern, &chars[start..].iter().collect::<String>()))
+                .map_or(value.clone(), |start| chars[..start].iter().collect()))
+        }
+        ParamOp::Replace { pattern, replacement, all } => {
+            let value = value.unwrap_or_default();
+            let pattern = expand_pattern(pattern, shell)?;
+            let replacement = expand_word(replacement, shell)?;
+            Ok(replace_matches(&value, &pattern, &replacement, *all))
+        }
+        ParamOp::Substring { offset, length } => {
+            let chars: Vec<char> = value.unwrap_or_default().chars().collect();
+            let len = chars.len() as i64;
+            let offset = substring_number(offset, shell)?;
+            // a negative offset counts back from the end
+            let start = if offset < 0 { (len + offset).max(0) } else { offset.min(len) };
+            let end = match length {
+                None => len,
+                Some(length) => {
+                    let length = substring_number(length, shell)?;
+                    // a negative length is an offset back from the end
+                    if length < 0 { len + length } else { (start + length).min(len) }
+                }
+            };
+            if end < start {
+                eprintln!("{}: substring expression < 0", expr.name);
+                return Err(ExpandError);
+            }
+            Ok(chars[start as usize..end as usize].iter().collect())
+        }
+    }
+}
+
+// Replace the longest match of pattern at each position, left to right.
+// Without `all` only the first match is replaced.
+fn replace_matches(value: &str, pattern: &str, replacement: &str, all: bool) -> String {
+    if pattern.is_empty() {
+        return value.to_string();
+    }
+    let chars: Vec<char> = value.chars().collect();
+    let mut result = String::new();
+    let mut i = 0;
+    let mut replaced = false;
+    while i < chars.len() {
+        let end = if replaced && !all {
+            None
+        } else {
+            (i + 1..=chars.len()).rev()
+                .find(|&end| pattern_matches(pattern, &chars[i..end].iter().collect::<String>()))
+        };
+        match end {
+            Some(end) => {
+                result.push_str(replacement);
+                replaced = true;
+                i = end;
+            }
+            None => {
+                result.push(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    result
+}
+
+fn substring_number(word: &Word, shell: &mut Shell) -> Result<i64, ExpandError> {
+    let text = expand_word(word, shell)?;
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    text.parse().map_err(|_| {
+        eprintln!("{}: invalid number", text);
+        ExpandError
+    })
+}
+
+// The value of a parameter, or None when it is unset
+fn lookup_param(name: &str, shell: &Shell) -> Option<String> {
+    match name {
+        "?" => Some(shell.last_status.to_string()),
+        "$" => Some(shell.pid.to_string()),
+        "!" => shell.jobs.last_pid().map(|pid| pid.to_string()),
+        "#" => Some(shell.positional.len().to_string()),
+        "-" => Some(shell.option_flags()),
+        "0" => Some(shell.arg0.clone()),
+        "@" => Some(shell.positional.join(" ")),
+        "*" => Some(shell.positional.join(&ifs_separator(shell))),
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => {
+            let index: usize = name.parse().ok()?;
+            shell.positional.get(index.checked_sub(1)?).cloned()
+        }
+        _ => shell.get_var(name),
+    }
+}
+
+// What "$*" puts between the positional parameters: the first character of
+// $IFS, a space when it is unset, nothing when it is empty
+fn ifs_separator(shell: &Shell) -> String {
+    match shell.get_var("IFS") {
+        Some(ifs) => ifs.chars().next().map(String::from).unwrap_or_default(),
+        None => String::from(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(ifs: &str, pieces: &[(&str, bool)]) -> Vec<String> {
+        let mut splitter = FieldSplitter {
+            ifs: ifs.to_string(),
+            fields: Vec::new(),
+            current: Field::default(),
+            in_field: false,
+            after_space: false,
+        };
+        for (text, quoted) in pieces {
+            if *quoted { splitter.push_text(text, true) } else { splitter.push_split(text) }
+        }
+        splitter.finish().into_iter().map(|field| field.text).collect()
+    }
+
+    #[test]
+    fn field_splitting() {
+        assert_eq!(split(DEFAULT_IFS, &[("  a \t b\n", false)]), ["a", "b"]);
+        assert_eq!(split(DEFAULT_IFS, &[("", false)]), Vec::<String>::new());
+        assert_eq!(split(DEFAULT_IFS, &[("", true)]), [""]);
+        assert_eq!(split(DEFAULT_IFS, &[("x", true), (" a b", false), ("y", true)]), ["x", "a", "by"]);
+        assert_eq!(split(" :", &[("a::b : c:", false)]), ["a", "", "b", "c"]);
+        assert_eq!(split("", &[("a b", false)]), ["a b"]);
+    }
+
+    #[test]
+    fn empty_quoted_lists() {
+        let mut shell = Shell::new();
+        let list = crate::parser::parse("f \"$@\" \"${a[@]}\" \"x$@\" \"$*\" \"\"$@").unwrap();
+        let words = match &list.items[0].and_or.first.commands[0] {
+            crate::ast::Command::Simple(command) => command.words.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(expand_words(&words, &mut shell).unwrap(), ["f", "x", "", ""]);
+        shell.positional = vec![String::new()];
+        assert_eq!(expand_words(&words[..2], &mut shell).unwrap(), ["f", ""]);
+    }
+    #[test]
+    fn error_if_unset_aborts() {
+        let mut shell = Shell::new();
+        shell.interactive = true;
+        let list = crate::parser::parse("f ${V:?not set}").unwrap();
+        let words = match &list.items[0].and_or.first.commands[0] {
+            crate::ast::Command::Simple(command) => command.words.clone(),
+            _ => unreachable!(),
+        };
+        assert!(expand_words(&words, &mut shell).is_err());
+        assert_eq!(shell.control, Some(Control::Abort));
+    }
+    #[test]
+    fn special_params() {
+        let mut shell = Shell::new();
+        shell.positional = (1..=10).map(|n| format!("p{}", n)).collect();
+        assert_eq!(lookup_param("#", &shell).as_deref(), Some("10"));
+        assert_eq!(lookup_param("1", &shell).as_deref(), Some("p1"));
+        assert_eq!(lookup_param("10", &shell).as_deref(), Some("p10"));
+        assert_eq!(lookup_param("11", &shell), None);
+        assert_eq!(lookup_param("00", &shell), None);
+        shell.positional.truncate(3);
+        assert_eq!(lookup_param("@", &shell).as_deref(), Some("p1 p2 p3"));
+        shell.set_var("IFS", ",").unwrap();
+        assert_eq!(lookup_param("*", &shell).as_deref(), Some("p1,p2,p3"));
+        assert_eq!(lookup_param("$", &shell), Some(shell.pid.to_string()));
+        assert_eq!(lookup_param("!", &shell), None);
+        shell.jobs.add_job(4242, String::from("sleep 1 &"));
+        assert_eq!(lookup_param("!", &shell).as_deref(), Some("4242"));
+        assert_eq!(lookup_param("-", &shell).as_deref(), Some(""));
+        shell.options.noglob = true;
+        shell.interactive = true;
+        assert_eq!(lookup_param("-", &shell).as_deref(), Some("fi"));
+    }
+}
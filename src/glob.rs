@@ -0,0 +1,292 @@
+use std::fs;
+use std::path::Path;
+
+/// Expand a pathname pattern against the filesystem. Each `/`-separated
+/// component is matched against one directory level; names starting with `.`
+/// only match a component that starts with `.` too. With `globstar`, a `**`
+/// component matches any number of directory levels. Results are sorted; an
+/// empty result means nothing matched.
+pub fn glob(pattern: &str, globstar: bool) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![String::from("/")], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+
+    for (i, &component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+        let mut next = Vec::new();
+        for base in &paths {
+            if component.is_empty() {
+                // `a//b` or a trailing slash: the path so far has to be a directory
+                if is_dir(base) {
+                    next.push(format!("{}/", base.trim_end_matches('/')));
+                }
+            } else if globstar && component == "**" {
+                if !last {
+                    next.push(base.clone());
+                }
+                walk(base, last, &mut next);
+            } else if has_wildcards(component) {
+                for name in read_names(base) {
+                    if name.starts_with('.') && !component.starts_with('.') && !component.starts_with("\\.") {
+                        continue;
+                    }
+                    let path = join(base, &name);
+                    if pattern_matches(component, &name) && (last || is_dir(&path)) {
+                        next.push(path);
+                    }
+                }
+            } else {
+                let path = join(base, &unescape(component));
+                if Path::new(&path).symlink_metadata().is_ok() {
+                    next.push(path);
+                }
+            }
+        }
+        paths = next;
+    }
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Whether `pattern` has an unescaped `*`, `?` or `[`, i.e. needs matching
+/// rather than being a plain name.
+pub fn has_wildcards(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Remove the backslash escapes from a pattern, giving the text it matches.
+pub fn unescape(pattern: &str) -> String {
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        text.push(if c == '\\' { chars.next().unwrap_or('\\') } else { c });
+    }
+    text
+}
+
+// Every non-hidden path below base, for `**`. Only directories when more
+// components follow.
+fn walk(base: &str, include_files: bool, out: &mut Vec<String>) {
+    for name in read_names(base) {
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = join(base, &name);
+        // don't follow symlinked directories, they can loop
+        let real_dir = Path::new(&path).symlink_metadata().is_ok_and(|m| m.is_dir());
+        if real_dir || include_files {
+            out.push(path.clone());
+        }
+        if real_dir {
+            walk(&path, include_files, out);
+        }
+    }
+}
+
+fn read_names(dir: &str) -> Vec<String> {
+    let dir = if dir.is_empty() { "." } else { dir };
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn join(base: &str, name: &str) -> String {
+    if base.is_empty() || base.ends_with('/') { format!("{}{}", base, name) } else { format!("{}/{}", base, name) }
+}
+
+fn is_dir(path: &str) -> bool {
+    Path::new(if path.is_empty() { "." } else { path }).is_dir()
+}
+
+/// Match `text` against a shell pattern: `*`, `?`, bracket expressions
+/// (`[abc]`, `[a-z]`, `[!x]` / `[^x]`, `[[:digit:]]`) and backslash escapes.
+/// The whole text has to match.
+pub fn pattern_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut pi, mut ti) = (0, 0);
+    // Where the most recent `*` was and how much text it has swallowed so far,
+    // so a failed match can backtrack and let the star take one more char
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < text.len() {
+        if pi < pattern.len() {
+            let step = match pattern[pi] {
+                '*' => {
+                    star = Some((pi, ti));
+                    pi += 1;
+                    continue;
+                }
+                '?' => Some(pi + 1),
+                '[' => match match_bracket(&pattern, pi, text[ti]) {
+                    Some((true, next)) => Some(next),
+                    Some((false, _)) => None,
+                    None => (text[ti] == '[').then_some(pi + 1),
+                },
+                '\\' if pi + 1 < pattern.len() => (pattern[pi + 1] == text[ti]).then_some(pi + 2),
+                c => (c == text[ti]).then_some(pi + 1),
+            };
+            if let Some(next) = step {
+                pi = next;
+                ti += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((star_pi, star_ti)) => {
+                pi = star_pi + 1;
+                ti = star_ti + 1;
+                star = Some((star_pi, star_ti + 1));
+            }
+            None => return false,
+        }
+    }
+    while pi < pattern.len() && pattern[pi] == '*' {
+        pi += 1;
+    }
+    pi == pattern.len()
+}
+
+/// Backslash-escape the pattern characters in `text` so it only matches itself.
+pub fn escape_pattern(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Test `c` against the bracket expression opening at pattern[start]. Returns
+// whether it matched and the index just past the closing `]`, or None if the
+// `[` is never closed (it is then an ordinary character).
+fn match_bracket(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(pattern.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &ch = pattern.get(i)?;
+        // a `]` right after the opening bracket is a member, not the end
+        if ch == ']' && !first {
+            break;
+        }
+        first = false;
+
+        if ch == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                matched |= class_matches(&rest[..end], c);
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let (low, next) = if ch == '\\' { (*pattern.get(i + 1)?, i + 2) } else { (ch, i + 1) };
+        if pattern.get(next) == Some(&'-') && pattern.get(next + 1).is_some_and(|&h| h != ']') {
+            let (high, after) = match pattern[next + 1] {
+                '\\' => (*pattern.get(next + 2)?, next + 3),
+                h => (h, next + 2),
+            };
+            matched |= low <= c && c <= high;
+            i = after;
+        } else {
+            matched |= low == c;
+            i = next;
+        }
+    }
+    Some((matched != negate, i + 1))
+}
+
+fn class_matches(class: &str, c: char) -> bool {
+    match class {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(pattern_matches("*.rs", "main.rs"));
+        assert!(!pattern_matches("*.rs", "main.rs.bak"));
+        assert!(pattern_matches("a*b*c", "aXXbYYbc"));
+        assert!(pattern_matches("?x", "ax"));
+        assert!(!pattern_matches("?x", "x"));
+        assert!(pattern_matches("*", ""));
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(pattern_matches("[abc]1", "b1"));
+        assert!(pattern_matches("[!a-z]", "Q"));
+        assert!(!pattern_matches("[!a-z]", "q"));
+        assert!(pattern_matches("[]x]", "]"));
+        assert!(pattern_matches("[[:digit:]]*", "7up"));
+        assert!(pattern_matches("[", "["));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(pattern_matches("\\*", "*"));
+        assert!(!pattern_matches("\\*", "a"));
+        assert!(pattern_matches(&escape_pattern("a*[b]"), "a*[b]"));
+        assert!(has_wildcards("src/*.rs"));
+        assert!(!has_wildcards("a\\*b"));
+        assert_eq!(unescape("a\\*b"), "a*b");
+    }
+
+    #[test]
+    fn filesystem_matches_are_sorted_and_hide_dotfiles() {
+        let dir = std::env::temp_dir().join(format!("shell-glob-test-{}", std::process::id()));
+        let root = dir.to_str().unwrap().to_string();
+        fs::create_dir_all(dir.join("sub/deep")).unwrap();
+        for file in ["b.rs", "a.rs", ".hidden.rs", "sub/c.rs", "sub/deep/d.rs"] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let rel = |paths: Vec<String>| -> Vec<String> {
+            paths.iter().map(|p| p[root.len() + 1..].to_string()).collect()
+        };
+
+        assert_eq!(rel(glob(&format!("{}/*.rs", root), false)), ["a.rs", "b.rs"]);
+        assert_eq!(rel(glob(&format!("{}/.*.rs", root), false)), [".hidden.rs"]);
+        assert_eq!(rel(glob(&format!("{}/*/", root), false)), ["sub/"]);
+        assert_eq!(
+            rel(glob(&format!("{}/**/*.rs", root), true)),
+            ["a.rs", "b.rs", "sub/c.rs", "sub/deep/d.rs"]
+        );
+        assert!(glob(&format!("{}/*.none", root), false).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
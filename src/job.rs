@@ -10,11 +10,12 @@ pub struct Job {
 pub struct JobTable {
     next_id: usize,      // starts at 1; monotonically increases; never reused
     jobs: Vec<Job>,      // we keep completed too, but mark running=false
+    last_pid: Option<i32>, // most recently started background job, for $!
 }
 
 impl JobTable {
     pub fn new() -> Self {
-        Self { next_id: 1, jobs: Vec::new() }
+        Self { next_id: 1, jobs: Vec::new(), last_pid: None }
     }
 
     pub fn has_active(&self) -> bool {
@@ -36,10 +37,16 @@ impl JobTable {
         let id = self.next_id;
         self.next_id += 1;
         self.jobs.push(Job { id, pid, cmdline, running: true });
+        self.last_pid = Some(pid);
         println!("[{}] {}", id, pid);
         Some(id)
     }
 
+    /// PID of the last job added, if any.
+    pub fn last_pid(&self) -> Option<i32> {
+        self.last_pid
+    }
+
     /// Print list of active background processes or “no active …”
     /// Format: `[Job number]+ [PID] [CMDLINE]`
     pub fn list_running(&self) {
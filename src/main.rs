@@ -1,26 +1,53 @@
-use std::io::{stdin, stdout, Write};
+use std::fs::File;
+use std::io::{self, stdout, BufRead, BufReader, Cursor, Write};
 use std::env;
-use executor::execute_command_with_jobs;
+use executor::execute_list;
+use parser::{parse, ParseError};
 
+mod arith;
+mod ast;
+mod brace;
 mod executor;
+mod expand;
+mod glob;
 mod parser;
 mod builtins;
 mod job;
+mod shell;
+mod vars;
 
-use builtins::{try_builtin, BuiltinResult, History};
-use job::JobTable;
+use builtins::{builtin_exit, exit_shell, is_builtin};
+use shell::Shell;
 
 fn main() {
-    let mut jobs = JobTable::new();
-    let mut history = History::new();
+    let mut shell = Shell::new();
+    let args: Vec<String> = env::args().collect();
+    let mut input = open_input(&args[1..], &mut shell);
+    // Ctrl-Ds in a row, for ignoreeof
+    let mut eofs = 0;
 
     loop {
         // poll for any completed background jobs before prompting
-        jobs.poll();
-        create_prompt();
+        shell.jobs.poll();
+        if shell.interactive {
+            create_prompt(&shell);
+        }
 
-        let mut input: String = String::new();
-        stdin().read_line(&mut input).unwrap();
+        let input = match read_command(&mut input, &shell) {
+            Ok(Some(input)) => input,
+            Ok(None) if shell.interactive && shell.options.ignoreeof && eofs < ignoreeof_limit(&shell) => {
+                eofs += 1;
+                println!();
+                eprintln!("Use \"exit\" to leave the shell.");
+                continue;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("shell: error reading input: {}", e);
+                std::process::exit(1);
+            }
+        };
+        eofs = 0;
         let command = input.trim();
 
         if command.is_empty() {
@@ -31,33 +58,162 @@ fn main() {
         //}
 
         //execute_command(command);
-        // very simple tokenization for built-in detection only
-        let simple_tokens: Vec<String> = command.split_whitespace().map(|s| s.to_string()).collect();
-        match try_builtin(command, &simple_tokens, &mut jobs, &mut history) {
-            BuiltinResult::Handled => { continue; }
-            BuiltinResult::NotHandled => {
-                // execute external / pipelines / redirections / background
-                execute_command_with_jobs(command, &mut jobs);
-                // treat as valid for history purposes
-                history.push_valid(command);
+        // builtins are dispatched per pipeline by the executor, so `cd x && ls` works
+        shell.last_status = match parse(&input) {
+            Ok(list) => execute_list(&list, &mut shell),
+            Err(e) => {
+                eprintln!("syntax error: {}", e);
+                // a script or -c command stops at a line it can't parse
+                if !shell.interactive {
+                    exit_shell(&mut shell, 2);
+                }
+                2
+            }
+        };
+        // an aborted command line is over; the next one starts afresh
+        shell.control = None;
+        // lines starting with a builtin aren't recorded, matching the old
+        // behaviour, and nor are script or -c lines
+        let first = command.split_whitespace().next().unwrap_or("");
+        if shell.from_stdin && !is_builtin(first) {
+            shell.history.push_valid(command);
+        }
+    }
+
+    // The end of stdin (Ctrl-D, or the end of piped commands) is `exit`: wait
+    // for background jobs and print the history. A script or -c command
+    // just ends with its last status.
+    if let Input::Stdin = input {
+        if shell.interactive {
+            println!();
+        }
+        builtin_exit(&mut shell.jobs, &shell.history, shell.last_status);
+    }
+    std::process::exit(shell.last_status);
+}
+
+// How many Ctrl-Ds in a row ignoreeof ignores: $IGNOREEOF, or 10 when that
+// isn't a number
+fn ignoreeof_limit(shell: &Shell) -> u32 {
+    shell.get_var("IGNOREEOF").and_then(|n| n.parse().ok()).unwrap_or(10)
+}
+
+// Where commands are read from. Stdin is read a byte at a time, like the
+// `read` builtin does, so a script piped or redirected in leaves the lines
+// after the current one for the commands it runs.
+enum Input {
+    Stdin,
+    Reader(Box<dyn BufRead>),
+}
+
+impl Input {
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        match self {
+            Input::Stdin => read_stdin_line(buf),
+            Input::Reader(reader) => reader.read_line(buf),
+        }
+    }
+}
+
+// Append one line from fd 0, newline included, without reading past it.
+// Returns the number of bytes read, 0 at the end of the input.
+fn read_stdin_line(buf: &mut String) -> io::Result<usize> {
+    let mut bytes = Vec::new();
+    loop {
+        let mut byte = 0u8;
+        let n = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
             }
+            return Err(err);
+        }
+        if n == 0 {
+            break;
+        }
+        bytes.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+    }
+    buf.push_str(&String::from_utf8_lossy(&bytes));
+    Ok(bytes.len())
+}
+
+// Set up from the command line:
+//   shell [-s] [args...]                 commands from stdin, prompting if it's a terminal
+//   shell -c command [name [args...]]    run one command string
+//   shell script [args...]               run a script file
+fn open_input(args: &[String], shell: &mut Shell) -> Input {
+    match args.first().map(String::as_str) {
+        None | Some("-s") => {
+            shell.positional = args.iter().skip(1).cloned().collect();
+            shell.interactive = unsafe { libc::isatty(libc::STDIN_FILENO) } == 1;
+            shell.from_stdin = true;
+            Input::Stdin
+        }
+        Some("-c") => {
+            let Some(command) = args.get(1) else {
+                eprintln!("shell: -c: option requires an argument");
+                std::process::exit(2);
+            };
+            if let Some(name) = args.get(2) {
+                shell.arg0 = name.clone();
+            }
+            shell.positional = args.iter().skip(3).cloned().collect();
+            Input::Reader(Box::new(Cursor::new(command.clone().into_bytes())))
+        }
+        Some(option) if option.starts_with('-') => {
+            eprintln!("shell: {}: invalid option", option);
+            std::process::exit(2);
+        }
+        Some(path) => match File::open(path) {
+            Ok(file) => {
+                shell.arg0 = path.to_string();
+                shell.positional = args[1..].to_vec();
+                Input::Reader(Box::new(BufReader::new(file)))
+            }
+            Err(e) => {
+                eprintln!("shell: {}: {}", path, e);
+                std::process::exit(127);
+            }
+        },
+    }
+}
+
+// Read one command, which may span several lines: while the input so far is
+// unfinished (an open quote, a here-document still waiting for its delimiter,
+// a trailing `|` or `&&`, an `if` without its `fi`) keep reading continuation
+// lines, after the $PS2 prompt when interactive. None at the end of the input.
+fn read_command(reader: &mut Input, shell: &Shell) -> io::Result<Option<String>> {
+    let mut input: String = String::new();
+    if reader.read_line(&mut input)? == 0 {
+        return Ok(None);
+    }
+    while let Err(ParseError::Incomplete(_)) = parse(&input) {
+        if shell.interactive {
+            print!("{}", shell.get_var("PS2").unwrap_or_else(|| String::from("> ")));
+            let _ = stdout().flush();
+        }
+        if reader.read_line(&mut input)? == 0 {
+            // nothing follows a final line continuation
+            if input.ends_with("\\\n") {
+                input.truncate(input.len() - 2);
+            }
+            break;
         }
     }
+    Ok(Some(input))
 }
 
-fn create_prompt() {
+fn create_prompt(shell: &Shell) {
 
     let prompt = ["USER","MACHINE","PWD"];
 
-    let user = match env::var(prompt[0]) {
-        Ok(val) => val,
-        Err(_) => String::new(),
-    };
+    let user = shell.get_var(prompt[0]).unwrap_or_default();
 
-    let machine = match env::var(prompt[1]) {
-        Ok(val) => val,
-        Err(_) => String::new(),
-    };
+    let machine = shell.get_var(prompt[1]).unwrap_or_default();
 
     let working_directory = match env::current_dir() {
         Ok(path) => path.display().to_string(),
@@ -1,143 +1,1706 @@
-use std::{env, ffi::CString, path::Path};
+use std::{ffi::CString, fmt, path::Path};
+use crate::ast::{AndOr, ArithForCommand, ArrayItem, Assignment, CaseCommand, CaseItem, CaseTerminator, Command, CompoundCommand, Connector, ForCommand, FunctionDef, IfCommand, List, ListItem, ParamExpr, ParamOp, Pipeline, RedirKind, Redirect, SimpleCommand, Subscript, WhileCommand, Word, WordPart};
+use crate::brace::brace_expand;
+use crate::shell::Shell;
+use crate::vars::{is_name, is_name_char};
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Word(String),
-    Argument(String),
-    EnvVar(String),
+    Word(Word),
+    IoNumber(i32), // the `2` in `2>file`: digits directly before a redirection
     Pipe,
-    RedirOut,
-    RedirIn,
+    Redir(RedirKind),
+    HereDoc { strip_tabs: bool }, // << or <<-; the word after it is replaced by the body
+    Newline,
     Background,
-    Tilde(String),
-}
-
-impl PartialEq for Token {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            // Compare only the discriminant (variant name) for Pipe
-            (Token::Pipe, Token::Pipe) => true,
-            // Compare RedirOut and RedirIn by their inner String values
-            // (Token::RedirOut(s1), Token::RedirOut(s2)) => s1 == s2,
-            // (Token::RedirIn(s1), Token::RedirIn(s2)) => s1 == s2,
-            // All other combinations are not equal
+    Semi,
+    AndIf,
+    OrIf,
+    Arith(Word), // (( expr )) in command position
+    ArithFor(Word, Word, Word), // ((init; condition; step)) after `for`
+    OpenParen,
+    CloseParen,
+    CaseEnd(CaseTerminator), // ;; ;& ;;&
+}
+
+// Operators recognised anywhere outside quotes. Longer operators must come
+// before their prefixes so the longest match wins.
+const OPERATORS: &[(&str, Token)] = &[
+    ("<<<", Token::Redir(RedirKind::HereString)),
+    ("<<-", Token::HereDoc { strip_tabs: true }),
+    ("&>>", Token::Redir(RedirKind::AppendAll)),
+    ("&&", Token::AndIf),
+    ("&>", Token::Redir(RedirKind::OutputAll)),
+    ("||", Token::OrIf),
+    (">>", Token::Redir(RedirKind::Append)),
+    (">&", Token::Redir(RedirKind::DupOutput)),
+    (">|", Token::Redir(RedirKind::Output)),
+    ("<<", Token::HereDoc { strip_tabs: false }),
+    ("<&", Token::Redir(RedirKind::DupInput)),
+    ("<>", Token::Redir(RedirKind::ReadWrite)),
+    (";;&", Token::CaseEnd(CaseTerminator::Continue)),
+    (";;", Token::CaseEnd(CaseTerminator::Break)),
+    (";&", Token::CaseEnd(CaseTerminator::FallThrough)),
+    (";", Token::Semi),
+    ("|", Token::Pipe),
+    (">", Token::Redir(RedirKind::Output)),
+    ("<", Token::Redir(RedirKind::Input)),
+    ("&", Token::Background),
+    ("(", Token::OpenParen),
+    (")", Token::CloseParen),
+];
+
+// Match an operator starting at chars[i], returning its token and length
+fn operator_at(chars: &[char], i: usize) -> Option<(Token, usize)> {
+    OPERATORS.iter().find_map(|(op, token)| {
+        let len = op.chars().count();
+        let matches = chars.len() >= i + len && op.chars().eq(chars[i..i + len].iter().copied());
+        if matches { Some((token.clone(), len)) } else { None }
+    })
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// The input ended in the middle of a command (open quote, missing
+    /// here-document delimiter, trailing `&&`...); more lines could finish it.
+    Incomplete(&'static str),
+    Syntax(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Incomplete(msg) => write!(f, "{}", msg),
+            ParseError::Syntax(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+fn unexpected(token: &Token) -> ParseError {
+    ParseError::Syntax(format!("unexpected token '{}'", token_text(token)))
+}
+
+/// A token together with the range of characters it was lexed from.
+struct Spanned {
+    token: Token,
+    start: usize,
+    end: usize,
+}
+
+struct Lexer<'a> {
+    chars: &'a [char],
+    i: usize,
+    tokens: Vec<Spanned>,
+    parts: Vec<WordPart>,
+    // A word can be made only of quotes (e.g. ""), so track it separately from parts
+    in_word: bool,
+    word_start: usize,
+    // Here-document delimiter words (token index, strip tabs) whose bodies
+    // start after the next newline
+    pending_heredocs: Vec<(usize, bool)>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(chars: &'a [char]) -> Self {
+        Self {
+            chars,
+            i: 0,
+            tokens: Vec::new(),
+            parts: Vec::new(),
+            in_word: false,
+            word_start: 0,
+            pending_heredocs: Vec::new(),
+        }
+    }
+
+    fn run(mut self) -> Result<Vec<Spanned>, ParseError> {
+        let chars = self.chars;
+        while self.i < chars.len() {
+            let c = chars[self.i];
+            if c == '\n' {
+                self.finish_word();
+                self.tokens.push(Spanned { token: Token::Newline, start: self.i, end: self.i + 1 });
+                self.i += 1;
+                self.read_heredoc_bodies()?;
+                continue;
+            }
+            if c.is_whitespace() {
+                self.finish_word();
+                self.i += 1;
+                continue;
+            }
+            // a `#` starting a word comments out the rest of the line, which
+            // also skips a script's `#!` line
+            if c == '#' && !self.in_word {
+                while self.i < chars.len() && chars[self.i] != '\n' {
+                    self.i += 1;
+                }
+                continue;
+            }
+            let arith_end = if c == '(' && chars.get(self.i + 1) == Some(&'(') && !self.in_word && (self.at_command_start() || self.after_for()) {
+                match find_closing_paren(chars, self.i + 2) {
+                    Some(end) if chars.get(end + 1) == Some(&')') => Some(end),
+                    // a single `)` closes a subshell nested in another, as
+                    // in `((cd /) && pwd)`, so this is two `(` operators
+                    Some(_) if !self.after_for() => None,
+                    Some(_) => return Err(ParseError::Syntax(String::from("expected `))' to close `(('"))),
+                    None => return Err(ParseError::Incomplete("unterminated arithmetic command")),
+                }
+            } else {
+                None
+            };
+            if let Some(end) = arith_end {
+                let inner = &chars[self.i + 2..end];
+                let token = if self.after_for() { lex_arith_for(inner)? } else { Token::Arith(lex_operand(inner, false)?) };
+                self.tokens.push(Spanned { token, start: self.i, end: end + 2 });
+                self.i = end + 2;
+                continue;
+            }
+            // Unquoted operators end the current word, so `ls>out` is three
+            // tokens. The `(` of `NAME=(...)` is part of the word.
+            let array_start = c == '(' && self.in_word && chars[self.i - 1] == '=' && is_assignment_prefix(&self.parts);
+            if !array_start && let Some((token, len)) = operator_at(chars, self.i) {
+                if matches!(token, Token::Redir(_) | Token::HereDoc { .. }) {
+                    self.take_io_number();
+                }
+                self.finish_word();
+                self.tokens.push(Spanned { token, start: self.i, end: self.i + len });
+                self.i += len;
+                continue;
+            }
+            // backslash-newline is a line continuation and disappears without
+            // starting a word; at the end of the input the next line is still to come
+            if c == '\\' && chars.get(self.i + 1) == Some(&'\n') {
+                if self.i + 2 == chars.len() {
+                    return Err(ParseError::Incomplete("line continuation at end of input"));
+                }
+                self.i += 2;
+                continue;
+            }
+            self.start_word();
+            match c {
+                '\\' => match chars.get(self.i + 1) {
+                    Some(&next) => {
+                        push_quoted(&mut self.parts, &next.to_string());
+                        self.i += 2;
+                    }
+                    // a lone backslash at the end of the input stays as it is
+                    None => {
+                        push_literal(&mut self.parts, '\\');
+                        self.i += 1;
+                    }
+                },
+                '\'' => {
+                    let start = self.i + 1;
+                    let end = match chars[start..].iter().position(|&ch| ch == '\'') {
+                        Some(offset) => start + offset,
+                        None => return Err(ParseError::Incomplete("unterminated single quote")),
+                    };
+                    let text: String = chars[start..end].iter().collect();
+                    push_quoted(&mut self.parts, &text);
+                    self.i = end + 1;
+                }
+                '"' => self.i = lex_double_quoted(chars, self.i + 1, &mut self.parts)?,
+                '$' => self.i = lex_dollar(chars, self.i, &mut self.parts, false)?,
+                // `NAME=(...)` assigns an array
+                '(' => {
+                    let end = match find_closing_paren(chars, self.i + 1) {
+                        Some(end) => end,
+                        None => return Err(ParseError::Incomplete("unterminated array assignment")),
+                    };
+                    self.parts.push(WordPart::Array(lex_array(&chars[self.i + 1..end])?));
+                    self.i = end + 1;
+                }
+                '`' => self.i = lex_backquote(chars, self.i, &mut self.parts, false)?,
+                '~' if self.parts.is_empty() => self.i = lex_tilde(chars, self.i, &mut self.parts, false),
+                // in an assignment, a tilde can also follow the `=` or a `:`
+                '~' if matches!(chars[self.i - 1], '=' | ':') && is_assignment_prefix(&self.parts) => {
+                    self.i = lex_tilde(chars, self.i, &mut self.parts, true)
+                }
+                _ => {
+                    push_literal(&mut self.parts, c);
+                    self.i += 1;
+                }
+            }
+        }
+        self.finish_word();
+        if !self.pending_heredocs.is_empty() {
+            return Err(ParseError::Incomplete("here-document missing its delimiter"));
+        }
+        Ok(self.tokens)
+    }
+
+    // Whether the next token would begin a command, which is where `((`
+    // starts an arithmetic command
+    fn at_command_start(&self) -> bool {
+        match self.tokens.last().map(|t| &t.token) {
+            None | Some(
+                Token::Newline | Token::Semi | Token::Pipe | Token::AndIf | Token::OrIf | Token::Background
+                | Token::OpenParen | Token::CloseParen | Token::CaseEnd(_),
+            ) => true,
+            // reserved words that a command follows, as in `then ((x))`
+            Some(Token::Word(word)) => {
+                matches!(word.as_slice(), [WordPart::Literal(text)] if COMMAND_PREFIXES.contains(&text.as_str()))
+            }
             _ => false,
         }
     }
+
+    // Whether the last token is `for`, where `((` starts the C-style form
+    fn after_for(&self) -> bool {
+        matches!(self.tokens.last().map(|t| &t.token), Some(Token::Word(word)) if *word == [WordPart::Literal(String::from("for"))])
+    }
+
+    fn start_word(&mut self) {
+        if !self.in_word {
+            self.in_word = true;
+            self.word_start = self.i;
+        }
+    }
+
+    // An unquoted all-digit word touching a redirection is the fd it applies to
+    fn take_io_number(&mut self) {
+        if let [WordPart::Literal(digits)] = self.parts.as_slice()
+            && digits.chars().all(|c| c.is_ascii_digit())
+            && let Ok(fd) = digits.parse()
+        {
+            self.tokens.push(Spanned { token: Token::IoNumber(fd), start: self.word_start, end: self.i });
+            self.parts.clear();
+            self.in_word = false;
+        }
+    }
+
+    // Push the collected parts as a word token, if a word is in progress
+    fn finish_word(&mut self) {
+        if !self.in_word {
+            return;
+        }
+        self.in_word = false;
+        if let Some(Spanned { token: Token::HereDoc { strip_tabs }, .. }) = self.tokens.last() {
+            self.pending_heredocs.push((self.tokens.len(), *strip_tabs));
+        }
+        let token = Token::Word(std::mem::take(&mut self.parts));
+        self.tokens.push(Spanned { token, start: self.word_start, end: self.i });
+    }
+
+    // Consume the bodies of the here-documents started on the line just ended,
+    // replacing each delimiter word with its body
+    fn read_heredoc_bodies(&mut self) -> Result<(), ParseError> {
+        for (index, strip_tabs) in std::mem::take(&mut self.pending_heredocs) {
+            let Token::Word(delimiter) = &self.tokens[index].token else { continue };
+            // Quoting any part of the delimiter turns off expansion in the body
+            let quoted = delimiter.iter().any(|part| matches!(part, WordPart::Quoted(_)));
+            let delimiter = word_source(delimiter);
+
+            let mut body = String::new();
+            loop {
+                if self.i >= self.chars.len() {
+                    return Err(ParseError::Incomplete("here-document missing its delimiter"));
+                }
+                let end = self.chars[self.i..].iter().position(|&c| c == '\n').map_or(self.chars.len(), |n| self.i + n);
+                let mut line: &[char] = &self.chars[self.i..end];
+                self.i = (end + 1).min(self.chars.len());
+                if strip_tabs {
+                    while let [first, rest @ ..] = line && *first == '\t' {
+                        line = rest;
+                    }
+                }
+                let line: String = line.iter().collect();
+                if line == delimiter {
+                    break;
+                }
+                body.push_str(&line);
+                body.push('\n');
+            }
+            let body_word = if quoted { vec![WordPart::Quoted(body)] } else { lex_heredoc_body(&body)? };
+            self.tokens[index].token = Token::Word(body_word);
+        }
+        Ok(())
+    }
 }
 
-pub fn tokenize(input: &str) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    // Split using whitespace, then match on special chars
-    for part in input.split_whitespace() {
+// The text a word was written as, minus its quoting; used for here-document delimiters
+fn word_source(word: &[WordPart]) -> String {
+    let mut text = String::new();
+    for part in word {
         match part {
-            "|" => tokens.push(Token::Pipe),
-            ">" => tokens.push(Token::RedirOut),
-            "<" => tokens.push(Token::RedirIn),
-            "&" => tokens.push(Token::Background),
-            // Only need to handle the ~ and ~/ case
-            t if t.starts_with('~') || t.starts_with("~/") => tokens.push(Token::Tilde(part.to_string())), 
-            p if p.starts_with('$') => tokens.push(Token::EnvVar(p[1..].to_string())),
-            a if a.starts_with('-') => tokens.push(Token::Argument(part.to_string())),
-            _ => tokens.push(Token::Word(part.to_string())),
-        }
-    }
-    tokens
-}
-
-pub fn expand_tokens(tokens: Vec<Token>) -> Vec<CString> {
-    let mut expanded_tokens: Vec<CString> = Vec::new();
-    let mut prev_token: Option<Token> = None;
-
-    for token in tokens {
-        match token {
-            Token::EnvVar(name) => {
-                if let Ok(val) = env::var(&name) {
-                    expanded_tokens.push(CString::new(val).unwrap());
-                    prev_token = Some(Token::EnvVar(name));
-                } else {
-                    expanded_tokens.push(CString::new("").unwrap());
-                    prev_token = Some(Token::EnvVar(name));
-                }
-            }
-            Token::Tilde(s) => {
-                let home = env::var("HOME").unwrap_or_else(|_| String::from("/"));
-                if s == "~" {
-                    expanded_tokens.push(CString::new(home).unwrap());
-                } else if let Some(rest) = s.strip_prefix("~/") {
-                    let full = format!("{}/{}", home, rest);
-                    expanded_tokens.push(CString::new(full).unwrap());
-                } else {
-                    expanded_tokens.push(CString::new(s.clone()).unwrap());
-                }
-                prev_token = Some(Token::Tilde(s));
-            }
-
-            Token::Word(s) => {
-                // If this is the first line or it follows a pipe, I need to search PATH for the executable
-                // If it follows a redirection, it's a filename, so just add it as an argument
-                // Otherwise, it's just an argument
-                if prev_token.is_none() || prev_token == Some(Token::Pipe) {
-                    let program = resolve_path(&s);
-                    expanded_tokens.push(program);
-                    prev_token = Some(Token::Word(s));
-                    continue;
-                } else {
-                    let arg = CString::new(s).unwrap();
-                    expanded_tokens.push(arg);
-                    prev_token = Some(Token::Word(String::new()));
-                    continue;
-                }
-            }
-            Token::Argument(s) => {
-                let arg = CString::new(s).unwrap();
-                expanded_tokens.push(arg);
-                prev_token = Some(Token::Argument(String::new()));
-            }
-            Token::Pipe => {
-                let pipe_token = CString::new("|").unwrap();
-                expanded_tokens.push(pipe_token);
-                prev_token = Some(Token::Pipe);
-            }
-            Token::RedirOut => {
-                // If it's a redirection, the next token should be a filename
-                expanded_tokens.push(CString::new(">").unwrap());
-                prev_token = Some(Token::RedirOut);
-            }
-            Token::RedirIn => {
-                // If it's a redirection, the next token should be a filename
-                expanded_tokens.push(CString::new("<").unwrap());
-                prev_token = Some(Token::RedirIn);
-            }
-            Token::Background => {
-                let bg_token = CString::new("&").unwrap();
-                expanded_tokens.push(bg_token);
-                prev_token = Some(Token::Background);
-            }
-        }
-    }
-    // For debugging purposes
-    // println!("Expanded Tokens: ");
-    // for t in expanded_tokens.iter() {
-    //     println!("{} ", t.to_str().unwrap());
-    // }
-    // println!();
-    expanded_tokens
-}
-
-fn resolve_path(s: &str) -> CString {
-    if let Ok(path) = env::var("PATH") {
+            WordPart::Literal(s) | WordPart::Quoted(s) => text.push_str(s),
+            WordPart::Param { expr, .. } => { text.push('$'); text.push_str(&expr.name); }
+            WordPart::Tilde(user) => { text.push('~'); text.push_str(user); }
+            WordPart::CommandSub { command, .. } => { text.push_str("$("); text.push_str(command); text.push(')'); }
+            WordPart::Arith(expr) => { text.push_str("$(("); text.push_str(&word_source(expr)); text.push_str("))"); }
+            WordPart::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| word_source(&item.value)).collect();
+                text.push('(');
+                text.push_str(&items.join(" "));
+                text.push(')');
+            }
+        }
+    }
+    text
+}
+
+// An unquoted here-document body expands like a double-quoted string, except
+// that quote characters have no special meaning
+fn lex_heredoc_body(body: &str) -> Result<Word, ParseError> {
+    let chars: Vec<char> = body.chars().collect();
+    let mut parts = Vec::new();
+    push_quoted(&mut parts, "");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => match chars.get(i + 1) {
+                Some('\n') => i += 2,
+                Some(&next) if matches!(next, '$' | '`' | '\\') => {
+                    push_quoted(&mut parts, &next.to_string());
+                    i += 2;
+                }
+                _ => {
+                    push_quoted(&mut parts, "\\");
+                    i += 1;
+                }
+            },
+            '$' => i = lex_dollar(&chars, i, &mut parts, true)?,
+            '`' => i = lex_backquote(&chars, i, &mut parts, true)?,
+            c => {
+                push_quoted(&mut parts, &c.to_string());
+                i += 1;
+            }
+        }
+    }
+    Ok(parts)
+}
+
+// The bare token stream, for checking the lexer on its own
+#[cfg(test)]
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let tokens = Lexer::new(&chars).run()?;
+    Ok(tokens.into_iter().map(|t| t.token).collect())
+}
+
+// Lex the body of a double-quoted string starting just after the opening quote.
+// Returns the index just past the closing quote.
+fn lex_double_quoted(chars: &[char], mut i: usize, parts: &mut Vec<WordPart>) -> Result<usize, ParseError> {
+    // "" still has to produce a (possibly empty) quoted piece
+    push_quoted(parts, "");
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return Ok(i + 1),
+            // Inside double quotes a backslash only escapes $ ` " \ and newline
+            '\\' => match chars.get(i + 1) {
+                Some('\n') => i += 2,
+                Some(&next) if matches!(next, '$' | '`' | '"' | '\\') => {
+                    push_quoted(parts, &next.to_string());
+                    i += 2;
+                }
+                _ => {
+                    push_quoted(parts, "\\");
+                    i += 1;
+                }
+            },
+            '$' => i = lex_dollar(chars, i, parts, true)?,
+            '`' => i = lex_backquote(chars, i, parts, true)?,
+            c => {
+                push_quoted(parts, &c.to_string());
+                i += 1;
+            }
+        }
+    }
+    Err(ParseError::Incomplete("unterminated double quote"))
+}
+
+// Lex a `$NAME`, `${...}` or `$(command)` at chars[i]. A `$` that doesn't start
+// any of them is kept as text.
+fn lex_dollar(chars: &[char], i: usize, parts: &mut Vec<WordPart>, quoted: bool) -> Result<usize, ParseError> {
+    let start = i + 1;
+    if chars.get(start) == Some(&'{') {
+        let end = match find_closing_brace(chars, start + 1) {
+            Some(end) => end,
+            None => return Err(ParseError::Incomplete("unterminated parameter expansion")),
+        };
+        let expr = parse_param(&chars[start + 1..end], quoted)?;
+        parts.push(WordPart::Param { expr: Box::new(expr), quoted });
+        return Ok(end + 1);
+    }
+    if chars.get(start) == Some(&'(') && chars.get(start + 1) == Some(&'(') {
+        match find_closing_paren(chars, start + 2) {
+            // `$((a) | b)` is a command substitution starting with a subshell
+            Some(end) if chars.get(end + 1) == Some(&')') => {
+                parts.push(WordPart::Arith(lex_operand(&chars[start + 2..end], false)?));
+                return Ok(end + 2);
+            }
+            Some(_) => {}
+            None => return Err(ParseError::Incomplete("unterminated arithmetic expansion")),
+        }
+    }
+    if chars.get(start) == Some(&'(') {
+        let end = match find_closing_paren(chars, start + 1) {
+            Some(end) => end,
+            None => return Err(ParseError::Incomplete("unterminated command substitution")),
+        };
+        let command = chars[start + 1..end].iter().collect();
+        parts.push(WordPart::CommandSub { command, quoted });
+        return Ok(end + 1);
+    }
+    // Special parameters are a single character
+    if let Some(&c) = chars.get(start) && is_special_param(c) {
+        parts.push(plain_param(c.to_string(), quoted));
+        return Ok(start + 1);
+    }
+    let mut end = start;
+    while end < chars.len() && is_name_char(chars[end], end == start) {
+        end += 1;
+    }
+    if end == start {
+        if quoted { push_quoted(parts, "$"); } else { push_literal(parts, '$'); }
+        return Ok(start);
+    }
+    parts.push(plain_param(chars[start..end].iter().collect(), quoted));
+    Ok(end)
+}
+
+fn plain_param(name: String, quoted: bool) -> WordPart {
+    WordPart::Param { expr: Box::new(ParamExpr { name, index: None, op: ParamOp::Plain }), quoted }
+}
+
+// Find the `}` that closes a `${` whose body starts at chars[start], skipping
+// over quoted text, escapes and nested braces
+fn find_closing_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i += chars.get(i + 1..)?.iter().position(|&c| c == '\'')? + 1,
+            '"' => {
+                i += 1;
+                while *chars.get(i)? != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Parse the inside of `${...}`: an optional `#` (length), the parameter name
+// and then an operator with its operand words
+fn parse_param(body: &[char], quoted: bool) -> Result<ParamExpr, ParseError> {
+    let bad = || ParseError::Syntax(format!("${{{}}}: bad substitution", body.iter().collect::<String>()));
+
+    // ${#NAME}, ${#NAME[@]} and ${!NAME[@]}
+    if body.len() > 1 && (body[0] == '#' || body[0] == '!') {
+        let (name, index, rest) = split_param_name(&body[1..]).ok_or_else(bad)??;
+        if !rest.is_empty() {
+            return Err(bad());
+        }
+        let op = match (body[0], &index) {
+            ('#', _) => ParamOp::Length,
+            (_, Some(Subscript::All | Subscript::Joined)) => ParamOp::Keys,
+            _ => return Err(bad()),
+        };
+        return Ok(ParamExpr { name, index, op });
+    }
+
+    let (name, index, rest) = split_param_name(body).ok_or_else(bad)??;
+    let rest_text: String = rest.iter().collect();
+
+    // The operand of the default-value forms keeps the quoting of the whole
+    // expansion; patterns are always live, even inside double quotes
+    let operand = |skip: usize| lex_operand(&rest[skip..], quoted);
+    let pattern = |skip: usize| lex_operand(&rest[skip..], false);
+
+    let op = if rest.is_empty() {
+        ParamOp::Plain
+    } else if let Some(kind) = ["-", "=", "?", "+"].iter().find_map(|op| {
+        if rest_text.starts_with(&format!(":{}", op)) { Some((op, true)) }
+        else if rest_text.starts_with(op) { Some((op, false)) }
+        else { None }
+    }) {
+        let (op, colon) = kind;
+        let word = operand(if colon { 2 } else { 1 })?;
+        match *op {
+            "-" => ParamOp::Default { word, colon },
+            "=" => ParamOp::Assign { word, colon },
+            "?" => ParamOp::Error { word, colon },
+            _ => ParamOp::Alternative { word, colon },
+        }
+    } else if rest_text.starts_with("##") {
+        ParamOp::RemovePrefix { pattern: pattern(2)?, longest: true }
+    } else if rest_text.starts_with('#') {
+        ParamOp::RemovePrefix { pattern: pattern(1)?, longest: false }
+    } else if rest_text.starts_with("%%") {
+        ParamOp::RemoveSuffix { pattern: pattern(2)?, longest: true }
+    } else if rest_text.starts_with('%') {
+        ParamOp::RemoveSuffix { pattern: pattern(1)?, longest: false }
+    } else if rest_text.starts_with('/') {
+        let all = rest_text.starts_with("//");
+        let body = &rest[if all { 2 } else { 1 }..];
+        let (pattern_chars, replacement_chars) = match find_unquoted(body, '/') {
+            Some(slash) => (&body[..slash], &body[slash + 1..]),
+            None => (body, &body[body.len()..]),
+        };
+        ParamOp::Replace {
+            pattern: lex_operand(pattern_chars, false)?,
+            replacement: lex_operand(replacement_chars, quoted)?,
+            all,
+        }
+    } else if rest_text.starts_with(':') {
+        let body = &rest[1..];
+        match find_unquoted(body, ':') {
+            Some(colon) => ParamOp::Substring {
+                offset: lex_operand(&body[..colon], false)?,
+                length: Some(lex_operand(&body[colon + 1..], false)?),
+            },
+            None => ParamOp::Substring { offset: lex_operand(body, false)?, length: None },
+        }
+    } else {
+        return Err(bad());
+    };
+    Ok(ParamExpr { name, index, op })
+}
+
+// Split the parameter name and any `[subscript]` off the front of a `${...}`
+// body, returning them with the rest of the body. None if there is no name.
+#[allow(clippy::type_complexity)]
+fn split_param_name(body: &[char]) -> Option<Result<(String, Option<Subscript>, &[char]), ParseError>> {
+    let name_len = param_name_len(body);
+    if name_len == 0 {
+        return None;
+    }
+    let name: String = body[..name_len].iter().collect();
+    if body.get(name_len) != Some(&'[') || !is_name_char(body[0], true) {
+        return Some(Ok((name, None, &body[name_len..])));
+    }
+    let mut depth = 0;
+    let close = (name_len + 1..body.len()).find(|&i| match body[i] {
+        '[' => { depth += 1; false }
+        ']' if depth == 0 => true,
+        ']' => { depth -= 1; false }
+        _ => false,
+    })?;
+    let subscript = &body[name_len + 1..close];
+    let index = match subscript {
+        ['@'] => Subscript::All,
+        ['*'] => Subscript::Joined,
+        _ => match lex_operand(subscript, false) {
+            Ok(word) => Subscript::Key(word),
+            Err(e) => return Some(Err(e)),
+        },
+    };
+    Some(Ok((name, Some(index), &body[close + 1..])))
+}
+
+// Length of the parameter name at the start of chars: an identifier, a
+// positional number or a single special character
+fn param_name_len(chars: &[char]) -> usize {
+    match chars.first() {
+        Some(&c) if is_name_char(c, true) => chars.iter().take_while(|&&c| is_name_char(c, false)).count(),
+        Some(c) if c.is_ascii_digit() => chars.iter().take_while(|c| c.is_ascii_digit()).count(),
+        Some(&c) if is_special_param(c) => 1,
+        _ => 0,
+    }
+}
+
+// Index of the first `sep` in chars that isn't quoted or escaped
+fn find_unquoted(chars: &[char], sep: char) -> Option<usize> {
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i += chars[i + 1..].iter().position(|&c| c == '\'')? + 1,
+            '"' => i += chars[i + 1..].iter().position(|&c| c == '"')? + 1,
+            '$' if chars.get(i + 1) == Some(&'{') => i += 1 + find_closing_brace(chars, i + 2)? - i,
+            c if c == sep => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Split the inside of `for ((init; condition; step))` into its three parts
+fn lex_arith_for(chars: &[char]) -> Result<Token, ParseError> {
+    let bad = || ParseError::Syntax(String::from("expected `for ((init; condition; step))'"));
+    let first = find_unquoted(chars, ';').ok_or_else(bad)?;
+    let rest = &chars[first + 1..];
+    let second = find_unquoted(rest, ';').ok_or_else(bad)?;
+    if find_unquoted(&rest[second + 1..], ';').is_some() {
+        return Err(bad());
+    }
+    Ok(Token::ArithFor(
+        lex_operand(&chars[..first], false)?,
+        lex_operand(&rest[..second], false)?,
+        lex_operand(&rest[second + 1..], false)?,
+    ))
+}
+
+// Lex an operand inside `${...}` (a default value, pattern or replacement)
+// into a word. Whitespace is part of the word; quotes, escapes and nested
+// expansions work as usual. Inside double quotes everything is quoted text.
+fn lex_operand(chars: &[char], quoted: bool) -> Result<Word, ParseError> {
+    let mut parts = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => {
+                let escaped = chars.get(i + 1).map_or(String::from("\\"), |c| c.to_string());
+                push_quoted(&mut parts, &escaped);
+                i += 2;
+            }
+            '\'' if !quoted => {
+                let end = i + 1 + chars[i + 1..].iter().position(|&c| c == '\'').unwrap_or(chars.len() - i - 1);
+                let text: String = chars[i + 1..end].iter().collect();
+                push_quoted(&mut parts, &text);
+                i = end + 1;
+            }
+            '"' => i = lex_double_quoted(chars, i + 1, &mut parts)?,
+            '$' => i = lex_dollar(chars, i, &mut parts, quoted)?,
+            '`' => i = lex_backquote(chars, i, &mut parts, quoted)?,
+            c if quoted => {
+                push_quoted(&mut parts, &c.to_string());
+                i += 1;
+            }
+            c => {
+                push_literal(&mut parts, c);
+                i += 1;
+            }
+        }
+    }
+    Ok(parts)
+}
+
+// Find the `)` that closes a `$(` whose body starts at chars[start], skipping
+// over quoted text, escapes and nested parentheses
+fn find_closing_paren(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '\'' => i += chars.get(i + 1..)?.iter().position(|&c| c == '\'')? + 1,
+            '`' => i += chars.get(i + 1..)?.iter().position(|&c| c == '`')? + 1,
+            '"' => {
+                i += 1;
+                while *chars.get(i)? != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+// Lex an old-style `command` substitution at chars[i]. Inside it a backslash
+// only escapes $, ` and \, which is how backquotes are nested.
+fn lex_backquote(chars: &[char], i: usize, parts: &mut Vec<WordPart>, quoted: bool) -> Result<usize, ParseError> {
+    let mut command = String::new();
+    let mut j = i + 1;
+    while j < chars.len() {
+        match chars[j] {
+            '`' => {
+                parts.push(WordPart::CommandSub { command, quoted });
+                return Ok(j + 1);
+            }
+            '\\' if matches!(chars.get(j + 1), Some('$' | '`' | '\\')) => {
+                command.push(chars[j + 1]);
+                j += 2;
+            }
+            c => {
+                command.push(c);
+                j += 1;
+            }
+        }
+    }
+    Err(ParseError::Incomplete("unterminated backquote"))
+}
+
+// Lex a tilde-prefix at chars[i]: the text up to the first `/` (or `:` in
+// an assignment) names the user whose home directory it expands to
+fn lex_tilde(chars: &[char], i: usize, parts: &mut Vec<WordPart>, assignment: bool) -> usize {
+    let mut end = i + 1;
+    while end < chars.len()
+        && !chars[end].is_whitespace()
+        && chars[end] != '/'
+        && !(assignment && chars[end] == ':')
+        && operator_at(chars, end).is_none()
+    {
+        if matches!(chars[end], '\'' | '"' | '\\' | '$') {
+            // a quoted tilde-prefix is not a tilde-prefix at all
+            push_literal(parts, '~');
+            return i + 1;
+        }
+        end += 1;
+    }
+    parts.push(WordPart::Tilde(chars[i + 1..end].iter().collect()));
+    end
+}
+
+// Whether the word lexed so far starts with `NAME=`, `NAME+=` or
+// `NAME[...]=`, making it an assignment
+fn is_assignment_prefix(parts: &[WordPart]) -> bool {
+    split_assignment(parts).is_some()
+}
+
+// `$?`, `$$`, `$!`, `$#`, `$@`, `$*`, `$-` and `$0`..`$9`; `${10}` needs braces
+fn is_special_param(c: char) -> bool {
+    matches!(c, '?' | '$' | '!' | '#' | '@' | '*' | '-') || c.is_ascii_digit()
+}
+
+fn push_literal(parts: &mut Vec<WordPart>, c: char) {
+    if let Some(WordPart::Literal(s)) = parts.last_mut() {
+        s.push(c);
+    } else {
+        parts.push(WordPart::Literal(c.to_string()));
+    }
+}
+
+fn push_quoted(parts: &mut Vec<WordPart>, text: &str) {
+    if let Some(WordPart::Quoted(s)) = parts.last_mut() {
+        s.push_str(text);
+    } else {
+        parts.push(WordPart::Quoted(text.to_string()));
+    }
+}
+
+/// Parse a full command line into a list. Grammar:
+///
+/// ```text
+/// list     := and_or ((';' | '&' | newline) and_or)* [';' | '&' | newline]
+/// and_or   := pipeline (('&&' | '||') newline* pipeline)*
+/// pipeline := command ('|' newline* command)*
+/// command  := (word | redirect)+ | compound redirect* | function
+/// function := name '(' ')' newline* compound | 'function' name ['(' ')'] newline* compound
+/// compound := '{' list '}' | '(' list ')'
+///           | 'if' list 'then' list ('elif' list 'then' list)* ['else' list] 'fi'
+///           | ('while' | 'until') list 'do' list 'done'
+///           | 'for' name ['in' word*] (';' | newline) newline* 'do' list 'done'
+///           | 'for' '((' arith ';' arith ';' arith '))' [';' | newline] newline* 'do' list 'done'
+///           | 'case' word newline* 'in' newline* case_item* 'esac'
+/// case_item := ['('] word ('|' word)* ')' list [';;' | ';&' | ';;&'] newline*
+/// redirect := [io_number] redir_op word
+/// ```
+///
+/// Reserved words like `if` and `fi` are only recognised as unquoted words
+/// where a command could start.
+pub fn parse(input: &str) -> Result<List, ParseError> {
+    let chars: Vec<char> = input.chars().collect();
+    let tokens = Lexer::new(&chars).run()?;
+    let mut parser = Parser { chars: &chars, tokens, pos: 0 };
+    parser.parse_list(&[])
+}
+
+// Words that are reserved where a command could start
+const RESERVED: &[&str] = &[
+    "if", "then", "elif", "else", "fi", "while", "until", "for", "do", "done", "case", "esac", "{", "}", "function",
+];
+
+// Reserved words that are followed by a command
+const COMMAND_PREFIXES: &[&str] = &["if", "then", "elif", "else", "while", "until", "do", "{"];
+
+struct Parser<'a> {
+    chars: &'a [char],
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    // Source text from the start of token `from` to the end of the last consumed token
+    fn text_since(&self, from: usize) -> String {
+        let start = self.tokens[from].start;
+        let end = self.tokens[self.pos - 1].end;
+        self.chars[start..end].iter().collect()
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(Token::Newline) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    // The reserved word at the current token, if it is one
+    fn peek_reserved(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Word(word)) => match word.as_slice() {
+                [WordPart::Literal(text)] => RESERVED.iter().find(|&&r| r == text).copied(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // Parse a list up to (but not including) one of the reserved words in
+    // `ends`; with no `ends` it runs to the end of the input. A list ending
+    // at `esac` also ends at `;;` and the other case terminators, and one
+    // ending at `)` stops at that operator.
+    fn parse_list(&mut self, ends: &[&str]) -> Result<List, ParseError> {
+        let mut items = Vec::new();
+        let case_item = ends.contains(&"esac");
+        let subshell = ends.contains(&")");
+        loop {
+            self.skip_newlines();
+            if self.peek_reserved().is_some_and(|word| ends.contains(&word))
+                || (case_item && matches!(self.peek(), Some(Token::CaseEnd(_))))
+                || (subshell && matches!(self.peek(), Some(Token::CloseParen)))
+            {
+                break;
+            }
+            if self.peek().is_none() {
+                if !ends.is_empty() {
+                    return Err(ParseError::Incomplete("unterminated compound command"));
+                }
+                break;
+            }
+            let start = self.pos;
+            let and_or = self.parse_and_or()?;
+            let text = self.text_since(start);
+            let background = match self.peek() {
+                Some(Token::Background) => { self.pos += 1; true }
+                Some(Token::Semi | Token::Newline) => { self.pos += 1; false }
+                Some(Token::CaseEnd(_)) if case_item => false,
+                Some(Token::CloseParen) if subshell => false,
+                Some(token) => return Err(unexpected(token)),
+                None => false,
+            };
+            items.push(ListItem { and_or, background, text });
+        }
+        Ok(List { items })
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let connector = match self.peek() {
+                Some(Token::AndIf) => Connector::And,
+                Some(Token::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((connector, self.parse_pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = vec![self.parse_command()?];
+        while let Some(Token::Pipe) = self.peek() {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.parse_command()?);
+        }
+        Ok(Pipeline { commands })
+    }
+
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        if let Some(Token::Arith(expr)) = self.peek() {
+            let expr = expr.clone();
+            self.pos += 1;
+            return Ok(Command::Arith(expr));
+        }
+        let start = self.pos;
+        if let Some(name) = self.function_name() {
+            // name ( )
+            self.pos += 3;
+            return self.parse_function_body(name, start);
+        }
+        if self.peek_reserved() == Some("function") {
+            self.pos += 1;
+            let name = match self.next() {
+                Some(Token::Word(word)) => match word.as_slice() {
+                    [WordPart::Literal(name)] if is_name(name) => name.clone(),
+                    _ => return Err(ParseError::Syntax(format!("`{}': not a valid identifier", word_source(&word)))),
+                },
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(ParseError::Incomplete("missing function name")),
+            };
+            if let (Some(Token::OpenParen), Some(Token::CloseParen)) = (self.peek(), self.tokens.get(self.pos + 1).map(|t| &t.token)) {
+                self.pos += 2;
+            }
+            return self.parse_function_body(name, start);
+        }
+        if let Some(Token::OpenParen) = self.peek() {
+            self.pos += 1;
+            let list = self.parse_list(&[")"])?;
+            self.pos += 1;
+            if list.items.is_empty() {
+                return Err(ParseError::Syntax(String::from("unexpected token ')'")));
+            }
+            let redirects = self.parse_redirects()?;
+            return Ok(Command::Compound(CompoundCommand::Subshell(list), redirects));
+        }
+        if let Some(word) = self.peek_reserved() {
+            self.pos += 1;
+            let compound = match word {
+                "{" => CompoundCommand::Group(self.parse_body(&["}"])?.0),
+                "if" => self.parse_if()?,
+                "while" | "until" => {
+                    let (condition, _) = self.parse_body(&["do"])?;
+                    let (body, _) = self.parse_body(&["done"])?;
+                    CompoundCommand::While(WhileCommand { condition, body, until: word == "until" })
+                }
+                "for" => self.parse_for()?,
+                "case" => self.parse_case()?,
+                _ => return Err(ParseError::Syntax(format!("unexpected token '{}'", word))),
+            };
+            let redirects = self.parse_redirects()?;
+            return Ok(Command::Compound(compound, redirects));
+        }
+        let mut assignments = Vec::new();
+        let mut words = Vec::new();
+        let mut redirects = Vec::new();
+        loop {
+            if let Some(redirect) = self.parse_redirect()? {
+                redirects.push(redirect);
+                continue;
+            }
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    let word = word.clone();
+                    self.pos += 1;
+                    // only words before the command name can be assignments
+                    match split_assignment(&word) {
+                        Some(assignment) if words.is_empty() => assignments.push(assignment),
+                        _ => words.extend(brace_expand(&word)),
+                    }
+                }
+                _ => break,
+            }
+        }
+        if assignments.is_empty() && words.is_empty() && redirects.is_empty() {
+            return Err(match self.peek() {
+                Some(token) => unexpected(token),
+                None => ParseError::Incomplete("unexpected end of input"),
+            });
+        }
+        Ok(Command::Simple(SimpleCommand { assignments, words, redirects }))
+    }
+
+    // The name of a `name ( )` function definition starting at the current token
+    fn function_name(&self) -> Option<String> {
+        let token = |offset: usize| self.tokens.get(self.pos + offset).map(|t| &t.token);
+        match (token(0)?, token(1)?, token(2)?) {
+            (Token::Word(word), Token::OpenParen, Token::CloseParen) => match word.as_slice() {
+                [WordPart::Literal(name)] if is_name(name) && !RESERVED.contains(&name.as_str()) => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // The compound command after `name()`; `start` is where the definition began
+    fn parse_function_body(&mut self, name: String, start: usize) -> Result<Command, ParseError> {
+        self.skip_newlines();
+        if self.peek().is_none() {
+            return Err(ParseError::Incomplete("missing function body"));
+        }
+        let body = match self.parse_command()? {
+            body @ Command::Compound(..) => body,
+            _ => return Err(ParseError::Syntax(format!("{}: function body must be a compound command", name))),
+        };
+        let text = self.text_since(start);
+        Ok(Command::Function(FunctionDef { name, body: Box::new(body), text }))
+    }
+
+    // A redirection and its target, if one starts at the current token
+    fn parse_redirect(&mut self) -> Result<Option<Redirect>, ParseError> {
+        let (fd, kind) = match self.peek() {
+            Some(Token::Redir(kind)) => (None, *kind),
+            Some(Token::HereDoc { .. }) => (None, RedirKind::HereDoc),
+            // the lexer only emits an io number right before a redirection
+            Some(Token::IoNumber(fd)) => {
+                let fd = *fd;
+                self.pos += 1;
+                match self.peek() {
+                    Some(Token::Redir(kind)) => (Some(fd), *kind),
+                    Some(Token::HereDoc { .. }) => (Some(fd), RedirKind::HereDoc),
+                    _ => return Err(ParseError::Syntax(String::from("expected redirection after fd number"))),
+                }
+            }
+            _ => return Ok(None),
+        };
+        self.pos += 1;
+        match self.next() {
+            Some(Token::Word(target)) => Ok(Some(Redirect { fd, kind, target })),
+            Some(token) => Err(unexpected(&token)),
+            None => Err(ParseError::Incomplete("missing file name after redirection")),
+        }
+    }
+
+    // The redirections after a compound command
+    fn parse_redirects(&mut self) -> Result<Vec<Redirect>, ParseError> {
+        let mut redirects = Vec::new();
+        while let Some(redirect) = self.parse_redirect()? {
+            redirects.push(redirect);
+        }
+        Ok(redirects)
+    }
+
+    // Consume the reserved word that ended a list. parse_list only stops
+    // early at one of the words it was given.
+    fn expect_reserved(&mut self) -> &'static str {
+        let word = self.peek_reserved().expect("list ended at a reserved word");
+        self.pos += 1;
+        word
+    }
+
+    // A list that must not be empty, as in `if ...; then`, ended by one of `ends`
+    fn parse_body(&mut self, ends: &[&str]) -> Result<(List, &'static str), ParseError> {
+        let list = self.parse_list(ends)?;
+        let end = self.expect_reserved();
+        if list.items.is_empty() {
+            return Err(ParseError::Syntax(format!("unexpected token '{}'", end)));
+        }
+        Ok((list, end))
+    }
+
+    // Everything after `for`, up to and including `done`
+    fn parse_for(&mut self) -> Result<CompoundCommand, ParseError> {
+        if let Some(Token::ArithFor(init, condition, step)) = self.peek() {
+            let (init, condition, step) = (init.clone(), condition.clone(), step.clone());
+            self.pos += 1;
+            if let Some(Token::Semi) = self.peek() {
+                self.pos += 1;
+            }
+            let body = self.parse_do_group()?;
+            return Ok(CompoundCommand::ArithFor(ArithForCommand { init, condition, step, body }));
+        }
+        let name = match self.next() {
+            Some(Token::Word(word)) => match word.as_slice() {
+                [WordPart::Literal(name)] if is_name(name) => name.clone(),
+                _ => return Err(ParseError::Syntax(format!("`{}': not a valid identifier", word_source(&word)))),
+            },
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(ParseError::Incomplete("missing name after `for'")),
+        };
+        self.skip_newlines();
+        let words = match self.peek() {
+            Some(Token::Word(word)) if *word == [WordPart::Literal(String::from("in"))] => {
+                self.pos += 1;
+                let mut words = Vec::new();
+                while let Some(Token::Word(word)) = self.peek() {
+                    words.extend(brace_expand(word));
+                    self.pos += 1;
+                }
+                Some(words)
+            }
+            _ => None,
+        };
+        match self.peek() {
+            Some(Token::Semi | Token::Newline) => self.pos += 1,
+            // `for name do` without a separator
+            _ if words.is_none() => {}
+            Some(token) => return Err(unexpected(token)),
+            None => return Err(ParseError::Incomplete("unterminated for loop")),
+        }
+        let body = self.parse_do_group()?;
+        Ok(CompoundCommand::For(ForCommand { name, words, body }))
+    }
+
+    // Everything after `case`, up to and including `esac`
+    fn parse_case(&mut self) -> Result<CompoundCommand, ParseError> {
+        let word = match self.next() {
+            Some(Token::Word(word)) => word,
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(ParseError::Incomplete("missing word after `case'")),
+        };
+        self.skip_newlines();
+        match self.next() {
+            Some(Token::Word(w)) if w == [WordPart::Literal(String::from("in"))] => {}
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(ParseError::Incomplete("expected `in'")),
+        }
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.peek_reserved() == Some("esac") {
+                self.pos += 1;
+                return Ok(CompoundCommand::Case(CaseCommand { word, items }));
+            }
+            if let Some(Token::OpenParen) = self.peek() {
+                self.pos += 1;
+            }
+            let mut patterns = Vec::new();
+            loop {
+                match self.next() {
+                    Some(Token::Word(pattern)) => patterns.push(pattern),
+                    Some(token) => return Err(unexpected(&token)),
+                    None => return Err(ParseError::Incomplete("unterminated case pattern")),
+                }
+                match self.next() {
+                    Some(Token::Pipe) => continue,
+                    Some(Token::CloseParen) => break,
+                    Some(token) => return Err(unexpected(&token)),
+                    None => return Err(ParseError::Incomplete("unterminated case pattern")),
+                }
+            }
+            let body = self.parse_list(&["esac"])?;
+            let terminator = match self.peek() {
+                Some(Token::CaseEnd(terminator)) => {
+                    let terminator = *terminator;
+                    self.pos += 1;
+                    terminator
+                }
+                // the last item can leave out `;;` before esac
+                _ => CaseTerminator::Break,
+            };
+            items.push(CaseItem { patterns, body, terminator });
+        }
+    }
+
+    // `do list done`, possibly after some newlines
+    fn parse_do_group(&mut self) -> Result<List, ParseError> {
+        self.skip_newlines();
+        match self.peek_reserved() {
+            Some("do") => self.pos += 1,
+            Some(word) => return Err(ParseError::Syntax(format!("unexpected token '{}'", word))),
+            None => match self.peek() {
+                Some(token) => return Err(unexpected(token)),
+                None => return Err(ParseError::Incomplete("expected `do'")),
+            },
+        }
+        Ok(self.parse_body(&["done"])?.0)
+    }
+
+    // Everything after `if`, up to and including `fi`
+    fn parse_if(&mut self) -> Result<CompoundCommand, ParseError> {
+        let mut branches = Vec::new();
+        loop {
+            let (condition, _) = self.parse_body(&["then"])?;
+            let (body, end) = self.parse_body(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+            match end {
+                "elif" => continue,
+                "else" => {
+                    let (otherwise, _) = self.parse_body(&["fi"])?;
+                    return Ok(CompoundCommand::If(IfCommand { branches, otherwise: Some(otherwise) }));
+                }
+                _ => return Ok(CompoundCommand::If(IfCommand { branches, otherwise: None })),
+            }
+        }
+    }
+}
+
+// `NAME=value`, `NAME+=value` or `NAME[index]=value`, where the name and
+// `=` are unquoted
+pub fn split_assignment(word: &[WordPart]) -> Option<Assignment> {
+    let WordPart::Literal(first) = word.first()? else { return None };
+    let name_len = first.chars().take_while(|&c| is_name_char(c, false)).count();
+    if !first.starts_with(|c| is_name_char(c, true)) {
+        return None;
+    }
+    let (name, rest) = first.split_at(name_len);
+    let name = name.to_string();
+
+    if let Some(after) = rest.strip_prefix('[') {
+        let mut parts = Vec::new();
+        if !after.is_empty() {
+            parts.push(WordPart::Literal(after.to_string()));
+        }
+        parts.extend(word[1..].iter().cloned());
+        let (index, append, value) = split_subscript(&parts)?;
+        return Some(Assignment { name, index: Some(index), append, value });
+    }
+    let (append, after) = match rest.strip_prefix("+=") {
+        Some(after) => (true, after),
+        None => (false, rest.strip_prefix('=')?),
+    };
+    let mut value = Vec::new();
+    if !after.is_empty() {
+        value.push(WordPart::Literal(after.to_string()));
+    }
+    value.extend(word[1..].iter().cloned());
+    Some(Assignment { name, index: None, append, value })
+}
+
+// Split `key]=value` (what follows a `[`) into the key, whether it was `]+=`,
+// and the value
+fn split_subscript(parts: &[WordPart]) -> Option<(Word, bool, Word)> {
+    let mut key = Vec::new();
+    for (i, part) in parts.iter().enumerate() {
+        if let WordPart::Literal(text) = part && let Some(close) = text.find(']') {
+            let after = &text[close + 1..];
+            let (append, rest) = match after.strip_prefix("+=") {
+                Some(rest) => (true, rest),
+                None => (false, after.strip_prefix('=')?),
+            };
+            if close > 0 {
+                key.push(WordPart::Literal(text[..close].to_string()));
+            }
+            let mut value = Vec::new();
+            if !rest.is_empty() {
+                value.push(WordPart::Literal(rest.to_string()));
+            }
+            value.extend(parts[i + 1..].iter().cloned());
+            return Some((key, append, value));
+        }
+        key.push(part.clone());
+    }
+    None
+}
+
+// Lex the items of an array assignment, the text between `=(` and `)`
+fn lex_array(chars: &[char]) -> Result<Vec<ArrayItem>, ParseError> {
+    let mut items = Vec::new();
+    for spanned in Lexer::new(chars).run()? {
+        match spanned.token {
+            Token::Word(word) => {
+                let keyed = match word.first() {
+                    Some(WordPart::Literal(text)) if text.starts_with('[') => {
+                        let mut parts = word.clone();
+                        parts[0] = WordPart::Literal(text[1..].to_string());
+                        if text.len() == 1 {
+                            parts.remove(0);
+                        }
+                        split_subscript(&parts)
+                    }
+                    _ => None,
+                };
+                match keyed {
+                    Some((key, _, value)) => items.push(ArrayItem { key: Some(key), value }),
+                    None => items.extend(brace_expand(&word).into_iter().map(|value| ArrayItem { key: None, value })),
+                }
+            }
+            Token::Newline => {}
+            token => return Err(unexpected(&token)),
+        }
+    }
+    Ok(items)
+}
+
+// Operator spelling for error messages
+fn token_text(token: &Token) -> &'static str {
+    match OPERATORS.iter().find(|(_, t)| t == token) {
+        Some((op, _)) => op,
+        None if *token == Token::Newline => "newline",
+        None => match token {
+            Token::Word(word) => match word.as_slice() {
+                [WordPart::Literal(text)] => RESERVED.iter().find(|&&r| r == text).copied().unwrap_or("word"),
+                _ => "word",
+            },
+            _ => "word",
+        },
+    }
+}
+
+// None when the name has a NUL byte, which no path can contain
+pub fn resolve_path(s: &str, shell: &Shell) -> Option<CString> {
+    if let Some(path) = shell.get_var("PATH") {
         let paths: Vec<&str> = path.split(':').collect();
 
         for path in paths.iter() {
             let full_path = &format!("{}/{}", path, s);
 
             if Path::new(&full_path).exists() {
-                let program = CString::new(full_path.as_str()).unwrap();
-                return program;
+                return CString::new(full_path.as_str()).ok();
             }
         }
     } else {
         println!("PATH environment variable is not set.");
     }
-    CString::new(s).unwrap()
+    CString::new(s).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(s: &str) -> Token {
+        Token::Word(vec![WordPart::Literal(s.to_string())])
+    }
+
+    #[test]
+    fn redirect_without_spaces() {
+        let tokens = tokenize("ls>out.txt").unwrap();
+        assert_eq!(tokens, vec![word("ls"), Token::Redir(RedirKind::Output), word("out.txt")]);
+    }
+
+    #[test]
+    fn input_and_pipe_without_spaces() {
+        let tokens = tokenize("cat<in|wc").unwrap();
+        assert_eq!(tokens, vec![word("cat"), Token::Redir(RedirKind::Input), word("in"), Token::Pipe, word("wc")]);
+    }
+
+    #[test]
+    fn trailing_background() {
+        let tokens = tokenize("sleep 5&").unwrap();
+        assert_eq!(tokens, vec![word("sleep"), word("5"), Token::Background]);
+    }
+
+    #[test]
+    fn mixed_spacing() {
+        let tokens = tokenize("a |b> c&").unwrap();
+        assert_eq!(
+            tokens,
+            vec![word("a"), Token::Pipe, word("b"), Token::Redir(RedirKind::Output), word("c"), Token::Background]
+        );
+    }
+
+    #[test]
+    fn redirection_operators() {
+        let tokens = tokenize("a 2>>log 2>&1 &>all <>rw 3<&- x2>y").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                word("a"),
+                Token::IoNumber(2), Token::Redir(RedirKind::Append), word("log"),
+                Token::IoNumber(2), Token::Redir(RedirKind::DupOutput), word("1"),
+                Token::Redir(RedirKind::OutputAll), word("all"),
+                Token::Redir(RedirKind::ReadWrite), word("rw"),
+                Token::IoNumber(3), Token::Redir(RedirKind::DupInput), word("-"),
+                word("x2"), Token::Redir(RedirKind::Output), word("y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn redirections_keep_source_order() {
+        let list = parse("cmd > f 2>&1").unwrap();
+        let Command::Simple(cmd) = &list.items[0].and_or.first.commands[0] else { unreachable!() };
+        let kinds: Vec<_> = cmd.redirects.iter().map(|r| (r.fd, r.kind)).collect();
+        assert_eq!(kinds, vec![(None, RedirKind::Output), (Some(2), RedirKind::DupOutput)]);
+    }
+
+    #[test]
+    fn list_operators() {
+        let tokens = tokenize("a&&b||c;d&e").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                word("a"), Token::AndIf, word("b"), Token::OrIf, word("c"),
+                Token::Semi, word("d"), Token::Background, word("e"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_list_structure() {
+        let list = parse("a | b > out && c; d &").unwrap();
+        assert_eq!(list.items.len(), 2);
+        let first = &list.items[0];
+        assert_eq!(first.text, "a | b > out && c");
+        assert_eq!(first.and_or.first.commands.len(), 2);
+        assert_eq!(first.and_or.rest.len(), 1);
+        assert!(!first.background);
+        assert!(list.items[1].background);
+    }
+
+    #[test]
+    fn quoted_pipe_is_an_argument() {
+        let list = parse("echo '|' \\>").unwrap();
+        let Command::Simple(cmd) = &list.items[0].and_or.first.commands[0] else { unreachable!() };
+        assert_eq!(list.items[0].and_or.first.commands.len(), 1);
+        assert_eq!(cmd.words.len(), 3);
+        assert!(cmd.redirects.is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("| a").is_err());
+        assert!(parse("a >\n").is_err());
+        assert!(parse("; a").is_err());
+        assert_eq!(parse("a &&"), Err(ParseError::Incomplete("unexpected end of input")));
+        assert!(matches!(parse("echo 'abc"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn heredoc_body_replaces_delimiter() {
+        let list = parse("cat <<EOF; cat <<-'END'\nhi $USER\nEOF\n\tplain $USER\n\tEND\n").unwrap();
+        let Command::Simple(first) = &list.items[0].and_or.first.commands[0] else { unreachable!() };
+        assert_eq!(first.redirects[0].kind, RedirKind::HereDoc);
+        assert_eq!(
+            first.redirects[0].target,
+            vec![
+                WordPart::Quoted("hi ".to_string()),
+                plain_param("USER".to_string(), true),
+                WordPart::Quoted("\n".to_string()),
+            ]
+        );
+        let Command::Simple(second) = &list.items[1].and_or.first.commands[0] else { unreachable!() };
+        assert_eq!(second.redirects[0].target, vec![WordPart::Quoted("plain $USER\n".to_string())]);
+    }
+
+    #[test]
+    fn command_substitution_nests() {
+        let tokens = tokenize("echo \"$(a \"$(b)\" ')')\" `c \\`d\\``").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                word("echo"),
+                Token::Word(vec![
+                    WordPart::Quoted(String::new()),
+                    WordPart::CommandSub { command: "a \"$(b)\" ')'".to_string(), quoted: true },
+                ]),
+                Token::Word(vec![WordPart::CommandSub { command: "c `d`".to_string(), quoted: false }]),
+            ]
+        );
+        assert!(matches!(parse("echo $(ls"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn parameter_expansion_forms() {
+        let op = |input: &str| {
+            let tokens = tokenize(input).unwrap();
+            match &tokens[0] {
+                Token::Word(parts) => match &parts[0] {
+                    WordPart::Param { expr, .. } => expr.op.clone(),
+                    other => panic!("not a parameter: {:?}", other),
+                },
+                other => panic!("not a word: {:?}", other),
+            }
+        };
+        let lit = |s: &str| vec![WordPart::Literal(s.to_string())];
+        assert_eq!(op("${HOME}"), ParamOp::Plain);
+        assert_eq!(op("${#HOME}"), ParamOp::Length);
+        assert_eq!(op("${X:-a b}"), ParamOp::Default { word: lit("a b"), colon: true });
+        assert_eq!(op("${X=y}"), ParamOp::Assign { word: lit("y"), colon: false });
+        assert_eq!(op("${X##*/}"), ParamOp::RemovePrefix { pattern: lit("*/"), longest: true });
+        assert_eq!(op("${X%.*}"), ParamOp::RemoveSuffix { pattern: lit(".*"), longest: false });
+        assert_eq!(
+            op("${X//a/b}"),
+            ParamOp::Replace { pattern: lit("a"), replacement: lit("b"), all: true }
+        );
+        assert_eq!(op("${X:1:2}"), ParamOp::Substring { offset: lit("1"), length: Some(lit("2")) });
+        assert_eq!(
+            tokenize("$10$#").unwrap(),
+            vec![Token::Word(vec![
+                plain_param("1".to_string(), false),
+                WordPart::Literal("0".to_string()),
+                plain_param("#".to_string(), false),
+            ])]
+        );
+        assert_eq!(op("${10}"), ParamOp::Plain);
+        assert!(parse("echo ${X!}").is_err());
+        assert!(matches!(parse("echo ${X"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn arithmetic_forms() {
+        let list = parse("(( x = 1 + 2 )) && echo $(( x*2 )) $((a) | b)").unwrap();
+        let and_or = &list.items[0].and_or;
+        assert_eq!(and_or.first.commands[0], Command::Arith(vec![WordPart::Literal(" x = 1 + 2 ".to_string())]));
+        let Command::Simple(echo) = &and_or.rest[0].1.commands[0] else { unreachable!() };
+        assert_eq!(echo.words[1], vec![WordPart::Arith(vec![WordPart::Literal(" x*2 ".to_string())])]);
+        assert!(matches!(echo.words[2][0], WordPart::CommandSub { .. }));
+        assert!(matches!(parse("echo $((1 +"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn tilde_prefixes() {
+        let tilde = |user: &str| WordPart::Tilde(user.to_string());
+        let lit = |text: &str| WordPart::Literal(text.to_string());
+        assert_eq!(tokenize("~alice/src").unwrap(), vec![Token::Word(vec![tilde("alice"), lit("/src")])]);
+        assert_eq!(
+            tokenize("PATH=~/bin:~+:$X").unwrap()[0],
+            Token::Word(vec![
+                lit("PATH="),
+                tilde(""),
+                lit("/bin:"),
+                tilde("+"),
+                lit(":"),
+                WordPart::Param { expr: Box::new(ParamExpr { name: "X".to_string(), index: None, op: ParamOp::Plain }), quoted: false },
+            ])
+        );
+        assert_eq!(tokenize("a:~").unwrap(), vec![Token::Word(vec![lit("a:~")])]);
+        assert_eq!(tokenize("\\~x").unwrap(), vec![Token::Word(vec![WordPart::Quoted("~".to_string()), lit("x")])]);
+    }
+
+    #[test]
+    fn assignments_only_before_command_name() {
+        let list = parse("A=1 B= \"C\"=2 echo D=4").unwrap();
+        let Command::Simple(cmd) = &list.items[0].and_or.first.commands[0] else { unreachable!() };
+        assert_eq!(
+            cmd.assignments,
+            vec![
+                Assignment { name: "A".to_string(), index: None, append: false, value: vec![WordPart::Literal("1".to_string())] },
+                Assignment { name: "B".to_string(), index: None, append: false, value: vec![] },
+            ]
+        );
+        assert_eq!(cmd.words.len(), 3);
+        assert_eq!(cmd.words[2], vec![WordPart::Literal("D=4".to_string())]);
+    }
+
+    #[test]
+    fn array_assignments() {
+        let lit = |text: &str| vec![WordPart::Literal(text.to_string())];
+        let list = parse("a=(x [2]=y) a[i+1]+=z echo ${a[@]}").unwrap();
+        let Command::Simple(cmd) = &list.items[0].and_or.first.commands[0] else { unreachable!() };
+        assert_eq!(
+            cmd.assignments[0].value,
+            vec![WordPart::Array(vec![
+                ArrayItem { key: None, value: lit("x") },
+                ArrayItem { key: Some(lit("2")), value: lit("y") },
+            ])]
+        );
+        assert_eq!(cmd.assignments[1].index, Some(lit("i+1")));
+        assert!(cmd.assignments[1].append);
+        let WordPart::Param { expr, .. } = &cmd.words[1][0] else { unreachable!() };
+        assert_eq!(expr.index, Some(Subscript::All));
+        assert!(matches!(parse("a=(x"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn if_commands() {
+        let list = parse("if a; then b; elif c\nthen d; else e; fi >out; echo fi").unwrap();
+        let Command::Compound(CompoundCommand::If(command), redirects) = &list.items[0].and_or.first.commands[0] else {
+            unreachable!()
+        };
+        assert_eq!(command.branches.len(), 2);
+        assert!(command.otherwise.is_some());
+        assert_eq!(redirects.len(), 1);
+        assert_eq!(list.items.len(), 2);
+        assert!(matches!(parse("if a; then b;"), Err(ParseError::Incomplete(_))));
+        assert!(matches!(parse("if a; then fi"), Err(ParseError::Syntax(_))));
+        assert!(matches!(parse("then b"), Err(ParseError::Syntax(_))));
+    }
+
+    #[test]
+    fn loops() {
+        let first = |input: &str| parse(input).unwrap().items[0].and_or.first.commands[0].clone();
+        let Command::Compound(CompoundCommand::For(command), _) = first("for x in a{1,2} b; do echo $x; done") else {
+            unreachable!()
+        };
+        assert_eq!((command.name.as_str(), command.words.map(|w| w.len())), ("x", Some(3)));
+        let Command::Compound(CompoundCommand::For(command), _) = first("for x\ndo :; done") else { unreachable!() };
+        assert_eq!(command.words, None);
+        let Command::Compound(CompoundCommand::ArithFor(command), _) = first("for ((i=0; i<3; i++)) do :; done") else {
+            unreachable!()
+        };
+        assert_eq!(command.step, vec![WordPart::Literal(" i++".to_string())]);
+        assert!(matches!(first("until a; do b; done | cat"), Command::Compound(CompoundCommand::While(w), _) if w.until));
+        assert_eq!(parse("while a; do b; done done"), Err(ParseError::Syntax(String::from("unexpected token 'done'"))));
+        assert!(matches!(parse("for 1x in a; do :; done"), Err(ParseError::Syntax(_))));
+        assert!(matches!(parse("while true; do"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn case_commands() {
+        let list = parse("case $x in\n(a|b) one;;\n*.c) ;&\n*) two ;;& esac").unwrap();
+        let Command::Compound(CompoundCommand::Case(command), _) = &list.items[0].and_or.first.commands[0] else {
+            unreachable!()
+        };
+        let items: Vec<_> = command.items.iter().map(|item| (item.patterns.len(), item.body.items.len(), item.terminator)).collect();
+        assert_eq!(
+            items,
+            [(2, 1, CaseTerminator::Break), (1, 0, CaseTerminator::FallThrough), (1, 1, CaseTerminator::Continue)]
+        );
+        assert!(matches!(parse("case x in a) b"), Err(ParseError::Incomplete(_))));
+        assert!(matches!(parse("case x in a b) c;; esac"), Err(ParseError::Syntax(_))));
+    }
+
+    #[test]
+    fn function_definitions() {
+        let list = parse("f() { a; } >out; function g { b; }").unwrap();
+        let names: Vec<_> = list
+            .items
+            .iter()
+            .map(|item| match &item.and_or.first.commands[0] {
+                Command::Function(def) => (def.name.as_str(), def.text.as_str()),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(names, [("f", "f() { a; } >out"), ("g", "function g { b; }")]);
+        assert!(matches!(parse("f() echo"), Err(ParseError::Syntax(_))));
+        assert!(matches!(parse("f() {"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn groups_and_subshells() {
+        let list = parse("(cd build && make) | { read x; echo $x; } >out").unwrap();
+        let commands = &list.items[0].and_or.first.commands;
+        assert!(matches!(&commands[0], Command::Compound(CompoundCommand::Subshell(list), redirects)
+            if list.items.len() == 1 && redirects.is_empty()));
+        assert!(matches!(&commands[1], Command::Compound(CompoundCommand::Group(list), redirects)
+            if list.items.len() == 2 && redirects.len() == 1));
+        assert!(matches!(parse("(a; (b)"), Err(ParseError::Incomplete(_))));
+        let list = parse("((cd /) && pwd)").unwrap();
+        let Command::Compound(CompoundCommand::Subshell(inner), _) = &list.items[0].and_or.first.commands[0] else {
+            unreachable!()
+        };
+        assert!(matches!(&inner.items[0].and_or.first.commands[0], Command::Compound(CompoundCommand::Subshell(_), _)));
+        assert_eq!(inner.items[0].and_or.rest.len(), 1);
+        assert!(matches!(parse("()"), Err(ParseError::Syntax(_))));
+        assert!(matches!(parse("a )"), Err(ParseError::Syntax(_))));
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(tokenize("#!/bin/shell\necho a#b # c").unwrap(), vec![Token::Newline, word("echo"), word("a#b")]);
+    }
+
+    #[test]
+    fn heredoc_waits_for_delimiter() {
+        assert!(matches!(parse("cat <<EOF\nline\n"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn line_continuation() {
+        assert!(matches!(parse("echo one \\\n"), Err(ParseError::Incomplete(_))));
+        assert_eq!(tokenize("echo one \\\n  two\n").unwrap(), vec![word("echo"), word("one"), word("two"), Token::Newline]);
+    }
+
+    #[test]
+    fn trailing_backslash() {
+        assert_eq!(tokenize("echo \\").unwrap(), vec![word("echo"), word("\\")]);
+        assert_eq!(tokenize("echo a\\").unwrap(), vec![word("echo"), word("a\\")]);
+        assert!(parse("echo \\").is_ok());
+    }
+
+    #[test]
+    fn quoting_and_concatenation() {
+        let quoted = |s: &str| WordPart::Quoted(s.to_string());
+        assert_eq!(
+            tokenize("foo\"bar\"'baz' '$HOME' \"a|b\" a\\ b ''").unwrap(),
+            vec![
+                Token::Word(vec![WordPart::Literal("foo".to_string()), quoted("barbaz")]),
+                Token::Word(vec![quoted("$HOME")]),
+                Token::Word(vec![quoted("a|b")]),
+                Token::Word(vec![WordPart::Literal("a".to_string()), quoted(" "), WordPart::Literal("b".to_string())]),
+                Token::Word(vec![quoted("")]),
+            ]
+        );
+        assert!(matches!(tokenize("echo \"abc"), Err(ParseError::Incomplete(_))));
+    }
+
+    #[test]
+    fn quoted_operators_stay_in_word() {
+        let tokens = tokenize("echo 'a|b' \\> \"x&y\"").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                word("echo"),
+                Token::Word(vec![WordPart::Quoted("a|b".to_string())]),
+                Token::Word(vec![WordPart::Quoted(">".to_string())]),
+                Token::Word(vec![WordPart::Quoted("x&y".to_string())]),
+            ]
+        );
+    }
 }
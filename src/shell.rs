@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use crate::arith::evaluate;
+use crate::ast::FunctionDef;
+use crate::builtins::History;
+use crate::job::JobTable;
+use crate::vars::{ArrayItem, Value, Var, Vars};
+
+/// State that lives for the whole shell session and is shared by the
+/// executor, the builtins and word expansion.
+pub struct Shell {
+    pub jobs: JobTable,
+    pub history: History,
+    pub last_status: i32, // exit status of the last command, exposed as $?
+    pub options: Options,
+    pub vars: Vars,
+    pub pid: i32,                // $$; stays the main shell's PID in subshells
+    pub arg0: String,            // $0
+    pub positional: Vec<String>, // $1, $2...
+    pub interactive: bool,       // reading commands from a terminal: prompts are printed
+    pub from_stdin: bool,        // reading commands from stdin: they go in the history, which `exit` prints
+    pub loop_depth: usize,       // loops being run, which `break` and `continue` can leave
+    // set by `break`, `continue` and `return`; commands are skipped until
+    // the loop or function it applies to has handled it. An abort is only
+    // cleared once the whole command line has been given up.
+    pub control: Option<Control>,
+    pub functions: BTreeMap<String, Rc<FunctionDef>>,
+    // one frame per running function: the variables its `local`s replaced,
+    // put back when it returns
+    pub locals: Vec<Vec<(String, Option<Var>)>>,
+}
+
+/// A pending `break n` or `continue n`, where the count is how many more
+/// enclosing loops it applies to, `return`, or an error that abandons the
+/// rest of the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Break(usize),
+    Continue(usize),
+    Return,
+    Abort,
+}
+
+/// Behaviour switches toggled with `set -o` / `shopt`.
+#[derive(Default)]
+pub struct Options {
+    pub noglob: bool,   // don't expand pathname patterns at all (set -f)
+    pub nullglob: bool, // a pattern that matches nothing expands to nothing
+    pub failglob: bool, // a pattern that matches nothing is an error
+    pub globstar: bool, // `**` matches any number of directories
+    pub ignoreeof: bool, // an interactive shell doesn't exit on Ctrl-D ($IGNOREEOF times in a row)
+}
+
+impl Options {
+    pub const NAMES: [&'static str; 5] = ["failglob", "globstar", "ignoreeof", "noglob", "nullglob"];
+
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "noglob" => Some(self.noglob),
+            "nullglob" => Some(self.nullglob),
+            "failglob" => Some(self.failglob),
+            "globstar" => Some(self.globstar),
+            "ignoreeof" => Some(self.ignoreeof),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "noglob" => Some(&mut self.noglob),
+            "nullglob" => Some(&mut self.nullglob),
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            "ignoreeof" => Some(&mut self.ignoreeof),
+            _ => None,
+        }
+    }
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self {
+            jobs: JobTable::new(),
+            history: History::new(),
+            last_status: 0,
+            options: Options::default(),
+            vars: Vars::from_env(),
+            pid: std::process::id() as i32,
+            arg0: std::env::args().next().unwrap_or_else(|| String::from("shell")),
+            positional: Vec::new(),
+            interactive: false,
+            from_stdin: false,
+            loop_depth: 0,
+            control: None,
+            functions: BTreeMap::new(),
+            locals: Vec::new(),
+        }
+    }
+
+    /// The option letters for $-: `f` after `set -f`, `i` when interactive
+    /// and `s` when reading commands from stdin. The other options have no
+    /// letter.
+    pub fn option_flags(&self) -> String {
+        [(self.options.noglob, 'f'), (self.interactive, 'i'), (self.from_stdin, 's')]
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| *flag)
+            .collect()
+    }
+
+    /// The value of a variable, or None when it is unset. For an array
+    /// this is element 0.
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        match self.vars.get(name)?.value.as_ref()? {
+            Value::Scalar(value) => Some(value.clone()),
+            Value::Indexed(map) => map.get(&0).cloned(),
+            Value::Assoc(map) => map.get("0").cloned(),
+        }
+    }
+
+    /// Assign a variable, applying its attributes: integer variables take the
+    /// value of the arithmetic expression, `-l` / `-u` fold the case. Fails
+    /// for a readonly variable. Assigning to an array sets element 0.
+    pub fn set_var(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let value = self.convert(name, value)?;
+        let var = self.vars.get_or_create(name);
+        match &mut var.value {
+            Some(Value::Indexed(map)) => { map.insert(0, value); }
+            Some(Value::Assoc(map)) => { map.insert(String::from("0"), value); }
+            slot => *slot = Some(Value::Scalar(value)),
+        }
+        Ok(())
+    }
+
+    /// The element `name[subscript]`. For an indexed array the subscript is an
+    /// arithmetic expression and a negative index counts back from the end.
+    pub fn get_element(&mut self, name: &str, subscript: &str) -> Result<Option<String>, String> {
+        Ok(match self.vars.get(name).and_then(|var| var.value.clone()) {
+            None => None,
+            Some(Value::Assoc(map)) => map.get(subscript).cloned(),
+            Some(Value::Indexed(map)) => {
+                let index = self.array_index(name, subscript, &map)?;
+                map.get(&index).cloned()
+            }
+            Some(Value::Scalar(value)) => (self.array_index(name, subscript, &BTreeMap::new())? == 0).then_some(value),
+        })
+    }
+
+    /// Assign `name[subscript]=value`, turning an unset or scalar variable into
+    /// an indexed array.
+    pub fn set_element(&mut self, name: &str, subscript: &str, value: &str) -> Result<(), String> {
+        let value = self.convert(name, value)?;
+        let current = self.vars.get(name).and_then(|var| var.value.clone());
+        let new = match current {
+            Some(Value::Assoc(mut map)) => {
+                map.insert(subscript.to_string(), value);
+                Value::Assoc(map)
+            }
+            other => {
+                let mut map = match other {
+                    Some(Value::Indexed(map)) => map,
+                    Some(Value::Scalar(old)) => BTreeMap::from([(0, old)]),
+                    _ => BTreeMap::new(),
+                };
+                let index = self.array_index(name, subscript, &map)?;
+                map.insert(index, value);
+                Value::Indexed(map)
+            }
+        };
+        self.vars.get_or_create(name).value = Some(new);
+        Ok(())
+    }
+
+    /// Remove `name[subscript]`.
+    pub fn unset_element(&mut self, name: &str, subscript: &str) -> Result<(), String> {
+        if self.vars.get(name).is_some_and(|var| var.attrs.readonly) {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        match self.vars.get(name).and_then(|var| var.value.clone()) {
+            Some(Value::Assoc(mut map)) => {
+                map.remove(subscript);
+                self.vars.get_or_create(name).value = Some(Value::Assoc(map));
+            }
+            Some(Value::Indexed(mut map)) => {
+                let index = self.array_index(name, subscript, &map)?;
+                map.remove(&index);
+                self.vars.get_or_create(name).value = Some(Value::Indexed(map));
+            }
+            Some(Value::Scalar(_)) if self.array_index(name, subscript, &BTreeMap::new())? == 0 => {
+                self.vars.get_or_create(name).value = None;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Every value of an array in key order; a scalar is a one-element array.
+    pub fn array_values(&self, name: &str) -> Vec<String> {
+        match self.vars.get(name).and_then(|var| var.value.as_ref()) {
+            None => Vec::new(),
+            Some(Value::Scalar(value)) => vec![value.clone()],
+            Some(Value::Indexed(map)) => map.values().cloned().collect(),
+            Some(Value::Assoc(map)) => map.values().cloned().collect(),
+        }
+    }
+
+    /// The indices or keys of an array, as for `${!name[@]}`.
+    pub fn array_keys(&self, name: &str) -> Vec<String> {
+        match self.vars.get(name).and_then(|var| var.value.as_ref()) {
+            None => Vec::new(),
+            Some(Value::Scalar(_)) => vec![String::from("0")],
+            Some(Value::Indexed(map)) => map.keys().map(|key| key.to_string()).collect(),
+            Some(Value::Assoc(map)) => map.keys().cloned().collect(),
+        }
+    }
+
+    /// Assign `name=(items)`, or append them with `name+=(items)`. Items
+    /// without a key take the index after the previous one; associative
+    /// arrays need a key for every item.
+    pub fn assign_array(&mut self, name: &str, items: Vec<ArrayItem>, append: bool) -> Result<(), String> {
+        if self.vars.get(name).is_some_and(|var| var.attrs.readonly) {
+            return Err(format!("{}: readonly variable", name));
+        }
+        let current = self.vars.get(name).and_then(|var| var.value.clone());
+        let new = match current {
+            Some(Value::Assoc(map)) => {
+                let mut map = if append { map } else { BTreeMap::new() };
+                for (key, value) in items {
+                    let Some(key) = key else {
+                        return Err(format!("{}: {}: must use subscript when assigning associative array", name, value));
+                    };
+                    map.insert(key, self.convert(name, &value)?);
+                }
+                Value::Assoc(map)
+            }
+            current => {
+                let mut map = match current {
+                    Some(Value::Indexed(map)) if append => map,
+                    Some(Value::Scalar(old)) if append => BTreeMap::from([(0, old)]),
+                    _ => BTreeMap::new(),
+                };
+                let mut next = map.keys().next_back().map_or(0, |last| last + 1);
+                for (key, value) in items {
+                    let index = match key {
+                        Some(key) => self.array_index(name, &key, &map)?,
+                        None => next,
+                    };
+                    map.insert(index, self.convert(name, &value)?);
+                    next = index + 1;
+                }
+                Value::Indexed(map)
+            }
+        };
+        self.vars.get_or_create(name).value = Some(new);
+        Ok(())
+    }
+
+    // Evaluate an indexed-array subscript; negative ones count from the end
+    fn array_index(&mut self, name: &str, subscript: &str, map: &BTreeMap<i64, String>) -> Result<i64, String> {
+        let index = evaluate(subscript, self)?;
+        if index >= 0 {
+            return Ok(index);
+        }
+        let from_end = map.keys().next_back().map_or(0, |last| last + 1) + index;
+        if from_end < 0 {
+            return Err(format!("{}[{}]: bad array subscript", name, subscript));
+        }
+        Ok(from_end)
+    }
+
+    // Check a value can be assigned to `name` and apply its attributes
+    fn convert(&mut self, name: &str, value: &str) -> Result<String, String> {
+        let attrs = self.vars.get(name).map(|var| var.attrs).unwrap_or_default();
+        if attrs.readonly {
+            return Err(format!("{}: readonly variable", name));
+        }
+        let mut value = if attrs.integer { evaluate(value, self)?.to_string() } else { value.to_string() };
+        if attrs.lowercase {
+            value = value.to_lowercase();
+        } else if attrs.uppercase {
+            value = value.to_uppercase();
+        }
+        Ok(value)
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::ffi::CString;
+
+/// Attributes set with `declare`, `export` and `readonly`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Attrs {
+    pub exported: bool,  // passed to the environment of child processes
+    pub readonly: bool,  // can't be assigned or unset
+    pub integer: bool,   // assignments are evaluated arithmetically
+    pub lowercase: bool, // assignments are folded to lower case
+    pub uppercase: bool, // assignments are folded to upper case
+}
+
+/// What a variable holds. Arrays are sparse: `a[5]=x` on an empty array
+/// makes an array with one element.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(String),
+    Indexed(BTreeMap<i64, String>),
+    Assoc(BTreeMap<String, String>), // declare -A
+}
+
+/// Whether `text` can name a variable or function: a letter or `_`, then
+/// letters, digits and `_`.
+pub fn is_name(text: &str) -> bool {
+    text.starts_with(|c| is_name_char(c, true)) && text.chars().all(|c| is_name_char(c, false))
+}
+
+/// Whether `c` can appear in a name, as its first character if `first`.
+pub fn is_name_char(c: char, first: bool) -> bool {
+    c == '_' || c.is_ascii_alphabetic() || (!first && c.is_ascii_digit())
+}
+
+/// An array element or plain value from `(a [k]=b ...)`, key and value
+/// already expanded.
+pub type ArrayItem = (Option<String>, String);
+
+/// A variable. `value` is None for a name that has attributes but was never
+/// assigned (`export X`, `declare -i n`); it counts as unset.
+#[derive(Debug, Clone)]
+pub struct Var {
+    pub value: Option<Value>,
+    pub attrs: Attrs,
+}
+
+impl Var {
+    /// The `declare` flags for this variable, e.g. "-ix", or "--" for none.
+    pub fn flags(&self) -> String {
+        let flags: String = [
+            (matches!(self.value, Some(Value::Indexed(_))), 'a'),
+            (matches!(self.value, Some(Value::Assoc(_))), 'A'),
+            (self.attrs.integer, 'i'),
+            (self.attrs.lowercase, 'l'),
+            (self.attrs.readonly, 'r'),
+            (self.attrs.uppercase, 'u'),
+            (self.attrs.exported, 'x'),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| *flag)
+        .collect();
+        if flags.is_empty() { String::from("--") } else { format!("-{}", flags) }
+    }
+}
+
+/// The shell's variables. They start out as a copy of the environment the
+/// shell was started with (all exported); after that the process environment
+/// is never touched and children get one built by `environment`.
+pub struct Vars {
+    map: HashMap<String, Var>,
+}
+
+impl Vars {
+    pub fn from_env() -> Self {
+        let exported = Attrs { exported: true, ..Attrs::default() };
+        let map = env::vars()
+            .map(|(name, value)| (name, Var { value: Some(Value::Scalar(value)), attrs: exported }))
+            .collect();
+        Self { map }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Var> {
+        self.map.get(name)
+    }
+
+    /// The variable called `name`, created unset if it doesn't exist yet.
+    pub fn get_or_create(&mut self, name: &str) -> &mut Var {
+        self.map.entry(name.to_string()).or_insert(Var { value: None, attrs: Attrs::default() })
+    }
+
+    /// Replace a variable wholesale, or remove it with None. Returns what was
+    /// there before so a temporary assignment can be undone.
+    pub fn replace(&mut self, name: &str, var: Option<Var>) -> Option<Var> {
+        match var {
+            Some(var) => self.map.insert(name.to_string(), var),
+            None => self.map.remove(name),
+        }
+    }
+
+    /// All variables sorted by name, for listings.
+    pub fn sorted(&self) -> Vec<(&String, &Var)> {
+        let mut vars: Vec<_> = self.map.iter().collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
+    }
+
+    /// `NAME=value` strings for every exported variable that is set, for
+    /// execve. Arrays can't be exported, and nor can a value with a NUL byte,
+    /// which is left out with a warning.
+    pub fn environment(&self) -> Vec<CString> {
+        self.map
+            .iter()
+            .filter(|(_, var)| var.attrs.exported)
+            .filter_map(|(name, var)| match &var.value {
+                Some(Value::Scalar(value)) => match CString::new(format!("{}={}", name, value)) {
+                    Ok(entry) => Some(entry),
+                    Err(_) => {
+                        eprintln!("warning: {}: not exported: value contains a null byte", name);
+                        None
+                    }
+                },
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(is_name("_a1"));
+        assert!(is_name("A"));
+        assert!(!is_name("1a"));
+        assert!(!is_name("a-b"));
+        assert!(!is_name(""));
+    }
+}
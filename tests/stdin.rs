@@ -0,0 +1,61 @@
+// Commands piped into the shell on stdin: exit statuses, and the end of the
+// input acting as `exit`.
+
+use std::fs::File;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn run(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn exit_status_in_dollar_question() {
+    let output = run("false\necho $?\ntrue\necho $?\nnot_a_command_xyz\necho $?\nfalse; echo $?\n");
+    assert!(stdout(&output).starts_with("1\n0\n127\n1\n"));
+}
+
+#[test]
+fn eof_prints_summary_and_keeps_status() {
+    // fewer than three commands: only the last one is printed
+    let output = run("echo a\nfalse\n");
+    assert_eq!(stdout(&output), "a\nfalse\n");
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = run("echo a\necho b\necho c\necho d\n");
+    assert_eq!(stdout(&output), "a\nb\nc\nd\necho b\necho c\necho d\n");
+    assert_eq!(output.status.code(), Some(0));
+
+    // builtins aren't recorded
+    let output = run("cd /\n");
+    assert_eq!(stdout(&output), "no valid commands\n");
+}
+
+#[test]
+fn exit_ends_input_early() {
+    let output = run("echo a\nexit 4\necho b\n");
+    assert_eq!(stdout(&output), "a\necho a\n");
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[test]
+fn read_error_exits_with_status_1() {
+    // reading a directory fails with EISDIR
+    let output = Command::new(env!("CARGO_BIN_EXE_shell"))
+        .stdin(File::open("/").unwrap())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("error reading input"));
+}